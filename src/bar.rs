@@ -29,6 +29,8 @@ use crate::{
     parse::{self, Placeholder},
     popup_visibility::VecPlaceholderExt,
     process,
+    protocol::i3bar,
+    source, state,
 };
 
 use config::VecStringRegexEx;
@@ -59,6 +61,19 @@ pub enum Button {
     ScrollDown,
 }
 
+impl Button {
+    /// Button number in i3bar click events.
+    fn i3bar_button(&self) -> u8 {
+        match self {
+            Button::Left => 1,
+            Button::Middle => 2,
+            Button::Right => 3,
+            Button::ScrollUp => 4,
+            Button::ScrollDown => 5,
+        }
+    }
+}
+
 impl std::fmt::Display for Button {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -98,6 +113,50 @@ impl<'a> parse::PlaceholderContext for PlaceholderContextWithValue<'a> {
             self.vars.get(key)
         }
     }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        self.vars.keys_with_prefix(prefix)
+    }
+
+    fn changed(&self, key: &str) -> bool {
+        // The same placeholder can be resolved with different values.
+        key == "value" || self.vars.changed(key)
+    }
+}
+
+/// Resolves `${instance}` and `${item.<var>}` for a copy of a repeated block.
+struct PlaceholderContextWithInstance<'a> {
+    vars: &'a dyn parse::PlaceholderContext,
+    prefix: &'a str,
+    instance: &'a String,
+}
+
+impl<'a> parse::PlaceholderContext for PlaceholderContextWithInstance<'a> {
+    fn get(&self, key: &str) -> Option<&String> {
+        if key == "instance" {
+            Some(self.instance)
+        } else if let Some(var) = key.strip_prefix("item.") {
+            self.vars
+                .get(&format!("{}{}.{}", self.prefix, self.instance, var))
+        } else {
+            self.vars.get(key)
+        }
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        self.vars.keys_with_prefix(prefix)
+    }
+
+    fn changed(&self, key: &str) -> bool {
+        if key == "instance" {
+            false
+        } else if let Some(var) = key.strip_prefix("item.") {
+            self.vars
+                .changed(&format!("{}{}.{}", self.prefix, self.instance, var))
+        } else {
+            self.vars.changed(key)
+        }
+    }
 }
 
 trait Block {
@@ -117,10 +176,62 @@ trait Block {
     fn handle_event(&self, event: &BlockEvent) -> anyhow::Result<()>;
     fn popup(&self) -> Option<config::PopupMode>;
     fn popup_value(&self) -> &Placeholder;
+    /// Vars shown at `x`, clicks there are sent to the i3bar commands producing them.
+    fn click_vars(&self, x: f64) -> Vec<String>;
+    /// Vars shown by the block, empty if it is hidden.
+    fn shown_vars(&self) -> Vec<String>;
+}
+
+fn placeholder_vars(placeholders: &[&Placeholder]) -> Vec<String> {
+    placeholders
+        .iter()
+        .flat_map(|placeholder| placeholder.var_names())
+        .map(String::from)
+        .collect()
+}
+
+/// Vars shown by a block through its display options.
+fn display_vars(display: &config::DisplayOptions<Placeholder>) -> Vec<String> {
+    let decorations = &display.decorations;
+    placeholder_vars(&[
+        &display.output_format,
+        &decorations.foreground,
+        &decorations.background,
+        &decorations.overline_color,
+        &decorations.underline_color,
+        &decorations.edgeline_color,
+    ])
 }
 
 trait DebugBlock: Block + Debug {}
 
+/// Adds items for new instances and drops the ones for instances that are gone.
+fn sync_items<T>(
+    items: &mut Vec<(String, T)>,
+    instances: Vec<String>,
+    mut build: impl FnMut() -> Option<T>,
+) -> bool {
+    if items
+        .iter()
+        .map(|(instance, _)| instance)
+        .eq(instances.iter())
+    {
+        return false;
+    }
+    let mut old_items: HashMap<String, T> = std::mem::take(items).into_iter().collect();
+    for instance in instances {
+        let item = match old_items.remove(&instance) {
+            Some(item) => item,
+            None => match build() {
+                Some(item) => item,
+                None => continue,
+            },
+        };
+        items.push((instance, item));
+    }
+    true
+}
+
 fn handle_block_event(
     event_handlers: &config::EventHandlers<Placeholder>,
     block_event: &BlockEvent,
@@ -214,6 +325,14 @@ impl Block for BaseBlock {
         self.inner_block.popup_value()
     }
 
+    fn click_vars(&self, x: f64) -> Vec<String> {
+        self.inner_block.click_vars(x)
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        self.inner_block.shown_vars()
+    }
+
     fn get_dimensions(&self) -> Dimensions {
         let inner_dim = self.inner_block.get_dimensions();
         let radius = if self.separator_type.is_some() {
@@ -516,6 +635,19 @@ impl Block for TextBlock {
     fn popup_value(&self) -> &Placeholder {
         &self.config.display.popup_value
     }
+
+    fn click_vars(&self, _x: f64) -> Vec<String> {
+        placeholder_vars(&[&self.config.input.value])
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        if !self.is_visible() {
+            return vec![];
+        }
+        let mut vars = placeholder_vars(&[&self.config.input.value]);
+        vars.extend(display_vars(&self.config.display));
+        vars
+    }
 }
 
 #[derive(Debug)]
@@ -781,6 +913,19 @@ impl Block for NumberBlock {
     fn popup_value(&self) -> &Placeholder {
         self.text_block.popup_value()
     }
+
+    fn click_vars(&self, _x: f64) -> Vec<String> {
+        placeholder_vars(&[&self.number.input.value])
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        if !self.is_visible() {
+            return vec![];
+        }
+        let mut vars = placeholder_vars(&[&self.number.input.value]);
+        vars.extend(self.text_block.shown_vars());
+        vars
+    }
 }
 
 #[derive(Debug)]
@@ -1012,6 +1157,23 @@ impl Block for EnumBlock {
     fn popup_value(&self) -> &Placeholder {
         &self.config.display.popup_value
     }
+
+    fn click_vars(&self, _x: f64) -> Vec<String> {
+        placeholder_vars(&[
+            &self.config.active,
+            &self.config.variants,
+            &self.config.input.value,
+        ])
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        if !self.is_visible() {
+            return vec![];
+        }
+        let mut vars = self.click_vars(0.0);
+        vars.extend(display_vars(&self.config.display));
+        vars
+    }
 }
 
 #[derive(Debug)]
@@ -1217,6 +1379,490 @@ impl Block for ImageBlock {
     fn popup_value(&self) -> &Placeholder {
         &self.config.display.popup_value
     }
+
+    fn click_vars(&self, _x: f64) -> Vec<String> {
+        placeholder_vars(&[&self.config.input.value])
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        if !self.is_visible() {
+            return vec![];
+        }
+        let mut vars = placeholder_vars(&[
+            &self.config.input.value,
+            &self.config.pixmap,
+            &self.config.icon_name,
+        ]);
+        vars.extend(display_vars(&self.config.display));
+        vars
+    }
+}
+
+#[derive(Debug)]
+struct RepeatBlock {
+    height: f64,
+    config: config::RepeatBlock<Placeholder>,
+    child: config::Block<Placeholder>,
+    items: Vec<(String, Box<dyn DebugBlock>)>,
+    popup_value: Placeholder,
+    dim: Dimensions,
+}
+
+impl DebugBlock for RepeatBlock {}
+
+impl RepeatBlock {
+    fn new(
+        height: f64,
+        config: config::RepeatBlock<Placeholder>,
+        child: config::Block<Placeholder>,
+    ) -> Self {
+        Self {
+            height,
+            config,
+            child,
+            items: vec![],
+            popup_value: Placeholder::default(),
+            dim: Dimensions {
+                width: 0.0,
+                height: 0.0,
+            },
+        }
+    }
+
+    /// Replaces `item.<var>` with the full name of the var of the instance.
+    fn item_vars(&self, instance: &str, vars: Vec<String>) -> Vec<String> {
+        let item_prefix = format!("{}{}.", self.config.prefix, instance);
+        vars.into_iter()
+            .map(|var| match var.strip_prefix("item.") {
+                Some(var) => format!("{}{}", item_prefix, var),
+                None => var,
+            })
+            .collect()
+    }
+
+    fn visible_items(&self) -> impl Iterator<Item = &dyn DebugBlock> {
+        self.items
+            .iter()
+            .map(|(_, item)| item.as_ref())
+            .filter(|item| item.is_visible())
+    }
+}
+
+impl Block for RepeatBlock {
+    fn handle_event(&self, event: &BlockEvent) -> anyhow::Result<()> {
+        match event {
+            BlockEvent::ButtonPress(button_press) => {
+                let mut pos: f64 = 0.0;
+                for item in self.visible_items() {
+                    let next_pos = pos + item.get_dimensions().width;
+                    if pos <= button_press.x && button_press.x <= next_pos {
+                        return item.handle_event(&BlockEvent::ButtonPress(ButtonPress {
+                            x: button_press.x - pos,
+                            ..button_press.clone()
+                        }));
+                    }
+                    pos = next_pos;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn get_dimensions(&self) -> Dimensions {
+        self.dim.clone()
+    }
+
+    fn update(
+        &mut self,
+        drawing_context: &mut drawing::Context,
+        vars: &dyn parse::PlaceholderContext,
+        fit_to_height: f64,
+    ) -> anyhow::Result<bool> {
+        let instances = self
+            .config
+            .instances(vars.keys_with_prefix(&self.config.prefix).into_iter());
+        let (height, child) = (self.height, &self.child);
+        let mut updates = vec![sync_items(&mut self.items, instances, || {
+            Bar::build_widget(height, child)
+        })];
+        for (instance, item) in self.items.iter_mut() {
+            let context = PlaceholderContextWithInstance {
+                vars,
+                prefix: &self.config.prefix,
+                instance,
+            };
+            updates.push(
+                item.update(drawing_context, &context, fit_to_height)
+                    .with_context(|| format!("instance {:?}", instance))?,
+            );
+        }
+        let mut dim = Dimensions {
+            width: 0.0,
+            height: 0.0,
+        };
+        for item in self.visible_items() {
+            let item_dim = item.get_dimensions();
+            dim.width += item_dim.width;
+            dim.height = dim.height.max(item_dim.height);
+        }
+        self.dim = dim;
+        Ok(updates.any_updated())
+    }
+
+    fn render(&mut self, drawing_context: &drawing::Context) -> anyhow::Result<()> {
+        let context = &drawing_context.context;
+        let mut x_offset: f64 = 0.0;
+        for (_, item) in self.items.iter_mut() {
+            if !item.is_visible() {
+                continue;
+            }
+            context.save()?;
+            context.translate(x_offset, 0.0);
+            item.render(drawing_context)?;
+            context.restore()?;
+            x_offset += item.get_dimensions().width;
+        }
+        Ok(())
+    }
+
+    fn is_visible(&self) -> bool {
+        self.visible_items().next().is_some()
+    }
+
+    fn popup(&self) -> Option<config::PopupMode> {
+        None
+    }
+
+    fn popup_value(&self) -> &Placeholder {
+        &self.popup_value
+    }
+
+    fn click_vars(&self, x: f64) -> Vec<String> {
+        let mut pos: f64 = 0.0;
+        for (instance, item) in self.items.iter().filter(|(_, item)| item.is_visible()) {
+            let next_pos = pos + item.get_dimensions().width;
+            if pos <= x && x <= next_pos {
+                return self.item_vars(instance, item.click_vars(x - pos));
+            }
+            pos = next_pos;
+        }
+        vec![]
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        // Items can be hidden by their own vars, so the prefix is always shown.
+        let mut vars = vec![self.config.prefix.clone()];
+        for (instance, item) in self.items.iter() {
+            vars.extend(self.item_vars(instance, item.shown_vars()));
+        }
+        vars
+    }
+}
+
+/// Resolves `${default.<key>}` of an i3bar block item to the decorations of the block.
+struct I3barItemContext<'a> {
+    item: PlaceholderContextWithInstance<'a>,
+    defaults: &'a HashMap<String, String>,
+    defaults_changed: bool,
+}
+
+impl<'a> parse::PlaceholderContext for I3barItemContext<'a> {
+    fn get(&self, key: &str) -> Option<&String> {
+        match key.strip_prefix("default.") {
+            Some(key) => self.defaults.get(key),
+            None => self.item.get(key),
+        }
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        self.item.keys_with_prefix(prefix)
+    }
+
+    fn changed(&self, key: &str) -> bool {
+        if key.starts_with("default.") {
+            self.defaults_changed
+        } else {
+            self.item.changed(key)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct I3barItemLayout {
+    index: usize,
+    x: f64,
+    /// At least the i3bar `min_width`.
+    width: f64,
+    /// Offset of the text within `width`, following the i3bar `align`.
+    offset: f64,
+    full_text: String,
+}
+
+/// Gap after an i3bar block that does not set `separator_block_width`, as in i3bar.
+const DEFAULT_SEPARATOR_BLOCK_WIDTH: f64 = 9.0;
+
+/// Shows every block of an i3bar command as a text block, styled by the command.
+#[derive(Debug)]
+struct I3barBlock {
+    height: f64,
+    config: config::I3barBlock<Placeholder>,
+    item_config: config::TextBlock<Placeholder>,
+    /// Keyed by `<name>` or `<name>.<instance>`, in the order of the command output.
+    items: Vec<(String, BaseBlock)>,
+    defaults: HashMap<String, String>,
+    layout: Vec<I3barItemLayout>,
+    dim: Dimensions,
+}
+
+impl DebugBlock for I3barBlock {}
+
+impl I3barBlock {
+    fn new(height: f64, config: config::I3barBlock<Placeholder>) -> Self {
+        let decorations = config::Decorations {
+            foreground: Placeholder::infallable(
+                r##"${if item.urgent == "true" then ${theme.urgent_foreground|def:#ffffff} else ${if item.color then item.color else default.foreground}}"##,
+            ),
+            background: Placeholder::infallable(
+                r##"${if item.urgent == "true" then ${theme.urgent_background|def:#900000} else ${if item.background then item.background else default.background}}"##,
+            ),
+            overline_color: Placeholder::infallable(
+                "${if item.border then item.border else default.overline_color}",
+            ),
+            underline_color: Placeholder::infallable(
+                "${if item.border then item.border else default.underline_color}",
+            ),
+            edgeline_color: Placeholder::default(),
+            line_width: config.display.decorations.line_width.or(Some(1.0)),
+        };
+        let item_config = config::TextBlock {
+            name: config.name.clone(),
+            display: config::DisplayOptions {
+                pango_markup: Some(true),
+                decorations: decorations.clone(),
+                hover_decorations: decorations,
+                show_if_matches: vec![],
+                popup_show_if_some: vec![],
+                popup: None,
+                ..config.display.clone()
+            },
+            input: config::Input {
+                value: Placeholder::infallable(
+                    r#"${if item.markup == "pango" then item.full_text else ${item.full_text|pango_escape}}"#,
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Self {
+            height,
+            config,
+            item_config,
+            items: vec![],
+            defaults: HashMap::new(),
+            layout: vec![],
+            dim: Dimensions {
+                width: 0.0,
+                height: 0.0,
+            },
+        }
+    }
+
+    fn update_layout(&mut self, vars: &dyn parse::PlaceholderContext, prefix: &str) {
+        let item_var = |key: &str, var: &str| vars.get(&format!("{}{}.{}", prefix, key, var));
+        let mut layout = Vec::with_capacity(self.items.len());
+        let mut x: f64 = 0.0;
+        let mut height: f64 = 0.0;
+        // i3bar `separator_block_width` of the previous visible item.
+        let mut gap: f64 = 0.0;
+        for (index, (key, item)) in self.items.iter().enumerate() {
+            let full_text = item_var(key, "full_text").cloned().unwrap_or_default();
+            if full_text.is_empty() || !item.is_visible() {
+                continue;
+            }
+            let number = |var| item_var(key, var).and_then(|value| value.parse::<f64>().ok());
+            let item_dim = item.get_dimensions();
+            let width = item_dim.width.max(number("min_width").unwrap_or_default());
+            let slack = width - item_dim.width;
+            let offset = match item_var(key, "align").map(String::as_str) {
+                Some("center") => slack / 2.0,
+                Some("right") => slack,
+                _ => 0.0,
+            };
+            if !layout.is_empty() {
+                x += gap;
+            }
+            layout.push(I3barItemLayout {
+                index,
+                x,
+                width,
+                offset,
+                full_text,
+            });
+            x += width;
+            gap = number("separator_block_width").unwrap_or(DEFAULT_SEPARATOR_BLOCK_WIDTH);
+            height = height.max(item_dim.height);
+        }
+        self.layout = layout;
+        self.dim = Dimensions { width: x, height };
+    }
+
+    fn item_at(&self, x: f64) -> Option<(&str, &I3barItemLayout)> {
+        self.layout
+            .iter()
+            .find(|layout| layout.x <= x && x <= layout.x + layout.width)
+            .map(|layout| (self.items[layout.index].0.as_str(), layout))
+    }
+}
+
+impl Block for I3barBlock {
+    fn handle_event(&self, event: &BlockEvent) -> anyhow::Result<()> {
+        match event {
+            BlockEvent::ButtonPress(button_press) => {
+                if let Some((key, layout)) = self.item_at(button_press.x) {
+                    return handle_block_event(
+                        &self.config.event_handlers,
+                        &BlockEvent::ButtonPress(ButtonPress {
+                            x: button_press.x - layout.x,
+                            ..button_press.clone()
+                        }),
+                        self.name(),
+                        &layout.full_text,
+                        vec![("BLOCK_INSTANCE".into(), key.into())],
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn get_dimensions(&self) -> Dimensions {
+        self.dim.clone()
+    }
+
+    fn update(
+        &mut self,
+        drawing_context: &mut drawing::Context,
+        vars: &dyn parse::PlaceholderContext,
+        fit_to_height: f64,
+    ) -> anyhow::Result<bool> {
+        let defaults_changed = [
+            self.config.display.update(vars)?,
+            self.config.event_handlers.update(vars)?,
+        ]
+        .any_updated();
+        if defaults_changed {
+            let decorations = &self.config.display.decorations;
+            self.defaults = [
+                ("foreground", &decorations.foreground),
+                ("background", &decorations.background),
+                ("overline_color", &decorations.overline_color),
+                ("underline_color", &decorations.underline_color),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.value.clone()))
+            .collect();
+        }
+        let prefix = format!("{}:", self.config.command);
+        let keys: Vec<String> = vars
+            .get(&format!("{}__blocks", prefix))
+            .map(|blocks| source::split_block_keys(blocks.as_str()))
+            .unwrap_or_default();
+        let (height, item_config) = (self.height, &self.item_config);
+        let mut updates = vec![
+            defaults_changed,
+            sync_items(&mut self.items, keys, || {
+                Some(BaseBlock::new(
+                    item_config.display.clone(),
+                    height,
+                    None,
+                    None,
+                    Box::new(TextBlock::new(item_config.clone())),
+                ))
+            }),
+        ];
+        for (key, item) in self.items.iter_mut() {
+            let context = I3barItemContext {
+                item: PlaceholderContextWithInstance {
+                    vars,
+                    prefix: &prefix,
+                    instance: key,
+                },
+                defaults: &self.defaults,
+                defaults_changed,
+            };
+            updates.push(
+                item.update(drawing_context, &context, fit_to_height)
+                    .with_context(|| format!("i3bar block {:?}", key))?,
+            );
+        }
+        self.update_layout(vars, &prefix);
+        Ok(updates.any_updated())
+    }
+
+    fn render(&mut self, drawing_context: &drawing::Context) -> anyhow::Result<()> {
+        let context = &drawing_context.context;
+        for layout in self.layout.iter() {
+            let (_, item) = &mut self.items[layout.index];
+            context.save()?;
+            context.translate(layout.x, 0.0);
+            let item_width = item.get_dimensions().width;
+            if layout.width > item_width {
+                // Extend the background of the item to its `min_width`.
+                context.save()?;
+                context.set_operator(cairo::Operator::Source);
+                drawing_context
+                    .set_source_rgba_background(&item.display_options.decorations.background)?;
+                context.rectangle(0.0, 0.0, layout.width, self.height);
+                context.fill()?;
+                context.restore()?;
+            }
+            context.translate(layout.offset, 0.0);
+            item.render(drawing_context)?;
+            context.restore()?;
+        }
+        Ok(())
+    }
+
+    fn is_visible(&self) -> bool {
+        let matches_ok = self.config.display.show_if_matches.all_match();
+        let popup_ok = self.config.display.popup_visible().unwrap_or(true);
+        !self.layout.is_empty() && matches_ok && popup_ok
+    }
+
+    fn popup(&self) -> Option<config::PopupMode> {
+        self.config.display.popup
+    }
+
+    fn popup_value(&self) -> &Placeholder {
+        &self.config.display.popup_value
+    }
+
+    fn click_vars(&self, x: f64) -> Vec<String> {
+        match self.item_at(x) {
+            Some((key, _)) => vec![format!("{}:{}.full_text", self.config.command, key)],
+            None => vec![],
+        }
+    }
+
+    fn shown_vars(&self) -> Vec<String> {
+        // Unlike `is_visible`, a block without output yet is shown.
+        let matches_ok = self.config.display.show_if_matches.all_match();
+        let popup_ok = self.config.display.popup_visible().unwrap_or(true);
+        if !matches_ok || !popup_ok {
+            return vec![];
+        }
+        let mut vars = vec![format!("{}:__blocks", self.config.command)];
+        vars.extend(display_vars(&self.config.display));
+        vars
+    }
 }
 
 struct BlockGroup {
@@ -1502,7 +2148,14 @@ pub struct Bar {
     right_group: BlockGroup,
     right_group_pos: f64,
     last_update_pointer_position: Option<(i16, i16)>,
+    /// `State::version` seen by the last successful update.
+    last_update_version: u64,
     notifier: notify::Notifier,
+    clicks: source::Clicks,
+    stopper: source::Stopper,
+    stopper_id: usize,
+    /// Commands whose vars decide what the bar shows, they are never paused.
+    visibility_commands: HashSet<String>,
 }
 
 impl Bar {
@@ -1510,10 +2163,13 @@ impl Bar {
         config: &config::Config<parse::Placeholder>,
         bar_config: config::Bar<Placeholder>,
         notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
     ) -> anyhow::Result<Self> {
         let left_group = Self::make_block_group(&bar_config.blocks_left, config, &bar_config);
         let center_group = Self::make_block_group(&bar_config.blocks_center, config, &bar_config);
         let right_group = Self::make_block_group(&bar_config.blocks_right, config, &bar_config);
+        let visibility_commands = Self::visibility_commands(config, &bar_config);
         Ok(Self {
             left_group,
             center_group,
@@ -1523,11 +2179,70 @@ impl Bar {
             center_group_pos: 0.0,
             right_group_pos: 0.0,
             last_update_pointer_position: None,
+            last_update_version: 0,
             bar_config,
             notifier,
+            clicks,
+            stopper_id: stopper.add_bar(),
+            stopper,
+            visibility_commands,
         })
     }
 
+    fn visibility_commands(
+        config: &config::Config<parse::Placeholder>,
+        bar_config: &config::Bar<Placeholder>,
+    ) -> HashSet<String> {
+        let mut vars: Vec<&str> = bar_config
+            .show_if_matches
+            .iter()
+            .map(|(expr, _)| expr)
+            .chain(bar_config.popup_show_if_some.iter())
+            .flat_map(|placeholder| placeholder.var_names())
+            .collect();
+        for var in config.vars.values() {
+            vars.extend(var.input.value.var_names());
+            vars.extend(var.function.as_ref().and_then(|function| function.var()));
+        }
+        let blocks = bar_config
+            .blocks_left
+            .iter()
+            .chain(bar_config.blocks_center.iter())
+            .chain(bar_config.blocks_right.iter())
+            .filter_map(|name| config.blocks.get(name));
+        for block in blocks {
+            vars.extend(block.visibility_vars());
+            if let config::Block::Repeat(repeat) = block {
+                if let Some(child) = config.blocks.get(&repeat.block) {
+                    vars.extend(child.visibility_vars());
+                }
+            }
+        }
+        vars.into_iter()
+            .filter_map(|var| var.split_once(':'))
+            .map(|(command, _)| command.to_string())
+            .collect()
+    }
+
+    /// Tells which commands the bar needs, the others can be paused.
+    pub fn report_shown(&self, visible: bool) {
+        let mut needed = self.visibility_commands.clone();
+        if visible {
+            let blocks = self
+                .left_group
+                .blocks
+                .iter()
+                .chain(self.center_group.blocks.iter())
+                .chain(self.right_group.blocks.iter());
+            needed.extend(
+                blocks
+                    .flat_map(|block| block.shown_vars())
+                    .filter_map(|var| var.split_once(':').map(|(command, _)| command.to_string())),
+            );
+        }
+        self.stopper.set_needed(self.stopper_id, needed);
+    }
+
     fn make_block_group(
         names: &[String],
         config: &config::Config<parse::Placeholder>,
@@ -1537,7 +2252,12 @@ impl Bar {
             blocks: names
                 .iter()
                 .filter_map(|name| config.blocks.get(name))
-                .filter_map(|block| Self::build_widget(bar_config, block))
+                .filter_map(|block| match block {
+                    config::Block::Repeat(repeat) => {
+                        Self::build_repeat(bar_config.height as f64, repeat, config)
+                    }
+                    block => Self::build_widget(bar_config.height as f64, block),
+                })
                 .collect(),
             layout: vec![],
             dimensions: Dimensions {
@@ -1549,29 +2269,45 @@ impl Bar {
     }
 
     fn build_widget(
-        bar_config: &config::Bar<Placeholder>,
+        height: f64,
         block: &config::Block<Placeholder>,
     ) -> Option<Box<dyn DebugBlock>> {
         match &block {
-            config::Block::Text(text) => Some(TextBlock::new_in_base_block(
-                bar_config.height as f64,
-                text.clone(),
-            )),
-            config::Block::Enum(e) => Some(Box::new(EnumBlock::new(
-                bar_config.height as f64,
-                e.clone(),
-            ))),
-            config::Block::Number(number) => Some(Box::new(NumberBlock::new(
-                bar_config.height as f64,
-                number.clone(),
-            ))),
+            config::Block::Text(text) => Some(TextBlock::new_in_base_block(height, text.clone())),
+            config::Block::Enum(e) => Some(Box::new(EnumBlock::new(height, e.clone()))),
+            config::Block::Number(number) => {
+                Some(Box::new(NumberBlock::new(height, number.clone())))
+            }
             #[cfg(feature = "image")]
-            config::Block::Image(image) => {
-                Some(ImageBlock::new(bar_config.height as f64, image.clone()))
+            config::Block::Image(image) => Some(ImageBlock::new(height, image.clone())),
+            config::Block::Repeat(repeat) => {
+                tracing::warn!("Repeat block {:?} cannot be repeated", repeat.name);
+                None
             }
+            config::Block::I3bar(i3bar) => Some(Box::new(I3barBlock::new(height, i3bar.clone()))),
         }
     }
 
+    fn build_repeat(
+        height: f64,
+        repeat: &config::RepeatBlock<Placeholder>,
+        config: &config::Config<parse::Placeholder>,
+    ) -> Option<Box<dyn DebugBlock>> {
+        let Some(child) = config.blocks.get(&repeat.block) else {
+            tracing::warn!(
+                "Block {:?} repeated by {:?} not found",
+                repeat.block,
+                repeat.name
+            );
+            return None;
+        };
+        Some(Box::new(RepeatBlock::new(
+            height,
+            repeat.clone(),
+            child.clone(),
+        )))
+    }
+
     fn error_block(bar_config: &config::Bar<Placeholder>) -> Box<dyn DebugBlock> {
         let name = ERROR_BLOCK_NAME.to_string();
         let config = config::TextBlock {
@@ -1585,7 +2321,7 @@ impl Bar {
             },
             ..Default::default()
         };
-        Self::build_widget(bar_config, &config::Block::Text(config)).unwrap()
+        Self::build_widget(bar_config.height as f64, &config::Block::Text(config)).unwrap()
     }
 
     pub fn set_error(
@@ -1617,9 +2353,15 @@ impl Bar {
     pub fn update(
         &mut self,
         drawing_context: &mut drawing::Context,
-        vars: &dyn parse::PlaceholderContext,
+        state: &state::State,
         pointer_position: Option<(i16, i16)>,
     ) -> anyhow::Result<BarUpdates> {
+        let last_update_version = self.last_update_version;
+        let changed = |name: &str| state.var_changed_since(name, last_update_version);
+        let vars = &parse::PlaceholderContextWithChanges {
+            vars: &state.vars,
+            changed: &changed,
+        };
         self.bar_config.background.update(vars)?;
         for show_if_match in self.bar_config.show_if_matches.iter_mut() {
             show_if_match.0.update(vars)?;
@@ -1672,6 +2414,7 @@ impl Bar {
             (None, None) => None,
         };
 
+        self.last_update_version = state.version;
         Ok(BarUpdates {
             block_updates,
             visible_from_vars,
@@ -1710,8 +2453,22 @@ impl Bar {
                 abs_y: y,
                 x: x - block_pos,
                 y,
-                button,
-            }))?
+                button: button.clone(),
+            }))?;
+            let dim = block.get_dimensions();
+            self.clicks.send(
+                &block.click_vars(x - block_pos),
+                i3bar::ClickEvent {
+                    button: button.i3bar_button(),
+                    x: x as i32,
+                    y: y as i32,
+                    relative_x: (x - block_pos) as i32,
+                    relative_y: y as i32,
+                    width: dim.width as i32,
+                    height: dim.height as i32,
+                    ..Default::default()
+                },
+            )?;
         }
 
         Ok(())
@@ -1819,3 +2576,9 @@ impl Bar {
         Ok(())
     }
 }
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        self.stopper.remove_bar(self.stopper_id);
+    }
+}
@@ -0,0 +1,927 @@
+// Copyright 2023 Oatbar Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Static checks of the config, run by `oatbar --check-config`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+
+use crate::{config, drawing, parse};
+
+const COLOR_KEYS: &[&str] = &[
+    "foreground",
+    "background",
+    "overline_color",
+    "underline_color",
+    "edgeline_color",
+];
+
+/// Keys of bar and block tables that hold plain strings rather than placeholders.
+const PLAIN_KEYS: &[&str] = &[
+    "name",
+    "type",
+    "inherit",
+    "popup",
+    "position",
+    "monitor",
+    "number_type",
+    "number_display",
+    "separator_type",
+    "enum_separator",
+    "fill",
+    "indicator",
+    "empty",
+    "text_bar_string",
+    "include",
+    "template",
+    "prefix",
+    "block",
+];
+
+const BAR_BLOCK_LISTS: &[&str] = &["blocks_left", "blocks_center", "blocks_right"];
+
+#[derive(Debug)]
+pub struct Problem {
+    pub file: PathBuf,
+    /// 1-based line and column.
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file.display(),
+                line,
+                column,
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+struct SourceFile {
+    path: PathBuf,
+    text: String,
+}
+
+impl SourceFile {
+    fn location(&self, span: Range<usize>) -> (usize, usize) {
+        config::location(&self.text, span.start)
+    }
+}
+
+fn get<'a, 'i>(table: &'a DeTable<'i>, key: &str) -> Option<&'a Spanned<DeValue<'i>>> {
+    table
+        .iter()
+        .find(|(k, _)| k.get_ref() == key)
+        .map(|(_, v)| v)
+}
+
+fn get_str<'a>(table: &'a DeTable<'_>, key: &str) -> Option<&'a str> {
+    match get(table, key).map(|v| v.get_ref()) {
+        Some(DeValue::String(s)) => Some(s.as_ref()),
+        _ => None,
+    }
+}
+
+fn get_bool(table: &DeTable<'_>, key: &str) -> bool {
+    matches!(
+        get(table, key).map(|v| v.get_ref()),
+        Some(DeValue::Boolean(true))
+    )
+}
+
+/// Entries of an array of tables, e.g. all `[[block]]` sections.
+fn tables<'a, 'i>(table: &'a DeTable<'i>, key: &str) -> Vec<&'a DeTable<'i>> {
+    match get(table, key).map(|v| v.get_ref()) {
+        Some(DeValue::Array(items)) => items
+            .iter()
+            .filter_map(|item| match item.get_ref() {
+                DeValue::Table(t) => Some(t),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    problems: Vec<Problem>,
+    /// Set when a problem also prevents the config from loading.
+    fatal: bool,
+    blocks: HashMap<String, Option<String>>,
+    default_blocks: HashSet<String>,
+    vars: HashSet<String>,
+    /// Names of commands and clocks, which set vars the same way.
+    commands: HashSet<String>,
+    clocks: HashSet<String>,
+    themes: HashSet<String>,
+    /// Keys of all themes, available as `${theme.<key>}`.
+    theme_keys: HashSet<String>,
+    /// Blocks rendered by `type = "repeat"` blocks.
+    repeated: HashSet<String>,
+    /// Set while checking a repeated block, which can use `${instance}` and `${item.<var>}`.
+    in_repeated: bool,
+}
+
+impl Checker {
+    fn report(&mut self, file: &SourceFile, span: Range<usize>, message: String) {
+        self.problems.push(Problem {
+            file: file.path.clone(),
+            location: Some(file.location(span)),
+            message,
+        });
+    }
+
+    fn collect_files(
+        &mut self,
+        path: &Path,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<SourceFile>,
+    ) {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if !visited.insert(path.clone()) {
+            return;
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.fatal = true;
+                self.problems.push(Problem {
+                    file: path,
+                    location: None,
+                    message: format!("unable to read: {}", e),
+                });
+                return;
+            }
+        };
+        let file = SourceFile { path, text };
+        let includes: Vec<(String, Range<usize>)> = match DeTable::parse(&file.text) {
+            Ok(table) => match get(table.get_ref(), "include").map(|v| v.get_ref()) {
+                Some(DeValue::Array(items)) => items
+                    .iter()
+                    .filter_map(|item| match item.get_ref() {
+                        DeValue::String(s) => Some((s.to_string(), item.span())),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            },
+            // Reported when the file is checked.
+            Err(_) => vec![],
+        };
+        let base_dir = file.path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        for (pattern, span) in includes {
+            match config::expand_glob(&base_dir, &pattern, &mut vec![]) {
+                Ok(paths) => {
+                    for include in paths {
+                        self.collect_files(&include, visited, files);
+                    }
+                }
+                Err(e) => {
+                    self.fatal = true;
+                    self.report(&file, span, format!("include {:?}: {:#}", pattern, e));
+                }
+            }
+        }
+        files.push(file);
+    }
+
+    fn collect_names(&mut self, root: &DeTable<'_>, command_index: &mut usize) {
+        for block in tables(root, "block") {
+            if let Some(name) = get_str(block, "name") {
+                self.blocks
+                    .insert(name.to_string(), get_str(block, "popup").map(String::from));
+            }
+            if get_str(block, "type") == Some("repeat") {
+                if let Some(child) = get_str(block, "block") {
+                    self.repeated.insert(child.to_string());
+                }
+            }
+        }
+        for default_block in tables(root, "default_block") {
+            if let Some(name) = get_str(default_block, "name") {
+                self.default_blocks.insert(name.to_string());
+            }
+        }
+        for var in tables(root, "var") {
+            if let Some(name) = get_str(var, "name") {
+                self.vars.insert(name.to_string());
+            }
+        }
+        for command in tables(root, "command") {
+            let name = get_str(command, "name")
+                .map(String::from)
+                .unwrap_or_else(|| format!("cm{}", command_index));
+            self.commands.insert(name);
+            *command_index += 1;
+        }
+        for clock in tables(root, "clock") {
+            let name = get_str(clock, "name").unwrap_or("clock");
+            self.commands.insert(name.to_string());
+            self.clocks.insert(name.to_string());
+        }
+        if let Some(DeValue::Table(themes)) = get(root, "theme").map(|v| v.get_ref()) {
+            for (name, theme) in themes.iter() {
+                self.themes.insert(name.get_ref().to_string());
+                if let DeValue::Table(theme) = theme.get_ref() {
+                    self.theme_keys
+                        .extend(theme.iter().map(|(key, _)| key.get_ref().to_string()));
+                }
+            }
+        }
+    }
+
+    fn var_known(&self, name: &str) -> bool {
+        if name == "value" || name == config::THEME_VAR || self.vars.contains(name) {
+            return true;
+        }
+        if let Some(key) = name.strip_prefix("theme.") {
+            return self.theme_keys.contains(key);
+        }
+        if self.in_repeated && (name == "instance" || name.starts_with("item.")) {
+            return true;
+        }
+        match name.split_once(':') {
+            Some(("_internal" | "env" | "xdg", _)) => true,
+            Some((command, _)) => self.commands.contains(command),
+            None => false,
+        }
+    }
+
+    fn check_placeholder(&mut self, file: &SourceFile, span: Range<usize>, value: &str) {
+        match parse::Placeholder::new(value) {
+            Ok(placeholder) => {
+                for name in placeholder.var_names() {
+                    if !self.var_known(name) {
+                        self.report(
+                            file,
+                            span.clone(),
+                            format!(
+                                "variable ${{{}}} is not produced by any [[var]] or [[command]]",
+                                name
+                            ),
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                self.fatal = true;
+                self.report(file, span, format!("{:#}", e));
+            }
+        }
+    }
+
+    fn check_regex(&mut self, file: &SourceFile, value: &Spanned<DeValue<'_>>) {
+        if let DeValue::String(s) = value.get_ref() {
+            if let Err(e) = regex::Regex::new(s) {
+                self.fatal = true;
+                self.report(file, value.span(), format!("invalid regex: {}", e));
+            }
+        }
+    }
+
+    fn check_pairs(
+        &mut self,
+        file: &SourceFile,
+        items: &[Spanned<DeValue<'_>>],
+        regex_index: Option<usize>,
+        placeholder_index: usize,
+    ) {
+        for item in items {
+            let DeValue::Array(pair) = item.get_ref() else {
+                continue;
+            };
+            if let Some(regex) = regex_index.and_then(|idx| pair.get(idx)) {
+                self.check_regex(file, regex);
+            }
+            if let Some(value) = pair.get(placeholder_index) {
+                if let DeValue::String(s) = value.get_ref() {
+                    self.check_placeholder(file, value.span(), s);
+                }
+            }
+        }
+    }
+
+    fn check_value(&mut self, file: &SourceFile, key: &str, value: &Spanned<DeValue<'_>>) {
+        match value.get_ref() {
+            DeValue::String(s) => {
+                let is_color = COLOR_KEYS.iter().any(|k| key.ends_with(k));
+                if is_color && !s.is_empty() && !s.contains("${") {
+                    if let Err(e) = drawing::Color::parse(s) {
+                        self.report(
+                            file,
+                            value.span(),
+                            format!("invalid color {:?} for {}: {}", s, key, e),
+                        );
+                    }
+                } else if !PLAIN_KEYS.contains(&key) {
+                    self.check_placeholder(file, value.span(), s);
+                }
+            }
+            DeValue::Array(items) => {
+                if key.ends_with("show_if_matches") {
+                    self.check_pairs(file, items, Some(1), 0);
+                } else if key.ends_with("replace") {
+                    self.check_pairs(file, items, Some(0), 1);
+                } else if key == "ramp" {
+                    self.check_pairs(file, items, None, 1);
+                } else if !PLAIN_KEYS.contains(&key) {
+                    for item in items.iter() {
+                        self.check_value(file, key, item);
+                    }
+                }
+            }
+            DeValue::Table(table) => self.check_table(file, table),
+            _ => {}
+        }
+    }
+
+    fn check_table(&mut self, file: &SourceFile, table: &DeTable<'_>) {
+        for (key, value) in table.iter() {
+            self.check_value(file, key.get_ref(), value);
+        }
+    }
+
+    /// Returns false if `inherit` refers to an unknown default block.
+    fn check_inherit(&mut self, file: &SourceFile, table: &DeTable<'_>) -> bool {
+        if let Some(inherit) = get(table, "inherit") {
+            if let DeValue::String(name) = inherit.get_ref() {
+                if !self.default_blocks.contains(name.as_ref()) {
+                    self.report(
+                        file,
+                        inherit.span(),
+                        format!("inherit refers to unknown default_block {:?}", name),
+                    );
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn check_repeat(&mut self, file: &SourceFile, table: &DeTable<'_>) {
+        if get_str(table, "type") != Some("repeat") {
+            return;
+        }
+        if let Some(child) = get(table, "block") {
+            if let DeValue::String(name) = child.get_ref() {
+                if !self.blocks.contains_key(name.as_ref()) {
+                    self.report(
+                        file,
+                        child.span(),
+                        format!("repeat refers to unknown block {:?}", name),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_i3bar(&mut self, file: &SourceFile, table: &DeTable<'_>) {
+        if get_str(table, "type") != Some("i3bar") {
+            return;
+        }
+        if let Some(command) = get(table, "command") {
+            if let DeValue::String(name) = command.get_ref() {
+                if !self.commands.contains(name.as_ref()) {
+                    self.report(
+                        file,
+                        command.span(),
+                        format!("i3bar block refers to unknown command {:?}", name),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_function(&mut self, file: &SourceFile, var: &DeTable<'_>) {
+        let Some(function) = get(var, "function") else {
+            return;
+        };
+        let DeValue::String(expression) = function.get_ref() else {
+            return;
+        };
+        match config::VarFunction::parse(expression) {
+            Ok(parsed) => {
+                if let Some(name) = parsed.var() {
+                    if !self.var_known(name) {
+                        self.report(
+                            file,
+                            function.span(),
+                            format!(
+                                "variable ${{{}}} is not produced by any [[var]] or [[command]]",
+                                name
+                            ),
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                self.fatal = true;
+                self.report(file, function.span(), format!("{:#}", e));
+            }
+        }
+    }
+
+    fn check_timeout(&mut self, file: &SourceFile, command: &DeTable<'_>) {
+        let Some(timeout) = get(command, "timeout") else {
+            return;
+        };
+        if get_str(command, "format") == Some("i3bar") {
+            self.report(
+                file,
+                timeout.span(),
+                "timeout kills i3bar commands that print nothing for so long, \
+                 e.g. while nothing changes"
+                    .into(),
+            );
+        }
+    }
+
+    fn check_bar(&mut self, file: &SourceFile, bar: &DeTable<'_>) {
+        // Such bars are only shown by blocks with `popup = "bar"`.
+        let popup_only = get_bool(bar, "popup")
+            && !get_bool(bar, "popup_at_edge")
+            && get(bar, "show_if_matches").is_none();
+        for list in BAR_BLOCK_LISTS {
+            let Some(DeValue::Array(names)) = get(bar, list).map(|v| v.get_ref()) else {
+                continue;
+            };
+            for name in names.iter() {
+                let DeValue::String(block_name) = name.get_ref() else {
+                    continue;
+                };
+                match self.blocks.get(block_name.as_ref()) {
+                    None => self.report(
+                        file,
+                        name.span(),
+                        format!("unknown block {:?} in {}", block_name, list),
+                    ),
+                    Some(Some(popup)) if popup_only && popup != "bar" => {
+                        let message = format!(
+                            "block {:?} has popup = {:?}, but this popup bar is only shown by popup = \"bar\" blocks",
+                            block_name, popup
+                        );
+                        self.report(file, name.span(), message)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (key, value) in bar.iter() {
+            if !BAR_BLOCK_LISTS.contains(&key.get_ref().as_ref()) {
+                self.check_value(file, key.get_ref(), value);
+            }
+        }
+    }
+
+    fn check_file(&mut self, file: &SourceFile, root: &DeTable<'_>) {
+        if let Some(theme) = get(root, "active_theme") {
+            if let DeValue::String(name) = theme.get_ref() {
+                if !self.themes.contains(name.as_ref()) {
+                    self.fatal = true;
+                    self.report(
+                        file,
+                        theme.span(),
+                        format!("active_theme refers to unknown theme {:?}", name),
+                    );
+                }
+            }
+        }
+        for bar in tables(root, "bar") {
+            self.check_bar(file, bar);
+        }
+        for block in tables(root, "block") {
+            self.check_inherit(file, block);
+            self.check_repeat(file, block);
+            self.check_i3bar(file, block);
+            self.in_repeated = get_str(block, "name").is_some_and(|n| self.repeated.contains(n));
+            self.check_table(file, block);
+            self.in_repeated = false;
+        }
+        for default_block in tables(root, "default_block") {
+            // Unlike blocks, default blocks with an unknown `inherit` fail to load.
+            if !self.check_inherit(file, default_block) {
+                self.fatal = true;
+            }
+            self.check_table(file, default_block);
+        }
+        for var in tables(root, "var") {
+            self.check_table(file, var);
+            self.check_function(file, var);
+        }
+        for command in tables(root, "command") {
+            self.check_timeout(file, command);
+            if let Some(name) = get(command, "name") {
+                if let DeValue::String(n) = name.get_ref() {
+                    if self.clocks.contains(n.as_ref()) {
+                        self.fatal = true;
+                        self.report(
+                            file,
+                            name.span(),
+                            format!("command name {:?} is used by a [[clock]] too", n),
+                        );
+                    }
+                }
+            }
+        }
+        for kind in ["command", "clock"] {
+            for source in tables(root, kind) {
+                if let Some(name) = get(source, "name") {
+                    if let DeValue::String(n) = name.get_ref() {
+                        if n == "env" || n == "xdg" {
+                            self.report(
+                                file,
+                                name.span(),
+                                format!("{} name {:?} is reserved for ${{{}:...}}", kind, n, n),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks the config at `path` together with all files it includes.
+pub fn check_config(path: &Path) -> Vec<Problem> {
+    let mut checker = Checker::default();
+    let mut files = vec![];
+    checker.collect_files(path, &mut HashSet::new(), &mut files);
+
+    let mut roots = vec![];
+    for file in files.iter() {
+        match DeTable::parse(&file.text) {
+            Ok(root) => roots.push((file, root)),
+            Err(e) => {
+                checker.fatal = true;
+                let message = e.message().to_string();
+                match e.span() {
+                    Some(span) => checker.report(file, span, message),
+                    None => checker.problems.push(Problem {
+                        file: file.path.clone(),
+                        location: None,
+                        message,
+                    }),
+                }
+            }
+        }
+    }
+
+    let mut command_index = 0;
+    for (_, root) in roots.iter() {
+        checker.collect_names(root.get_ref(), &mut command_index);
+    }
+    for (file, root) in roots.iter() {
+        checker.check_file(file, root.get_ref());
+    }
+
+    if !checker.fatal {
+        // Catches everything the checks above do not know about, e.g. wrong value types.
+        if let Err(e) = config::load_from(path) {
+            checker.problems.push(Problem {
+                file: path.to_path_buf(),
+                location: None,
+                message: format!("{:#}", e),
+            });
+        }
+    }
+    let mut problems = checker.problems;
+    problems.sort_by(|a, b| (&a.file, a.location).cmp(&(&b.file, b.location)));
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    fn check_str(name: &str, text: &str) -> Vec<String> {
+        let dir = write_files(&format!("check-{}", name), &[("config.toml", text)]);
+        check_config(&dir.join("config.toml"))
+            .into_iter()
+            .map(|p| {
+                let (line, column) = p.location.unwrap_or_default();
+                format!("{}:{}: {}", line, column, p.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let problems = check_str(
+            "valid",
+            r##"
+[[bar]]
+blocks_left = ["clock"]
+
+[[command]]
+name = "clock"
+command = "date"
+
+[[var]]
+name = "greeting"
+value = "hello ${clock:value}"
+
+[[default_block]]
+name = "dim"
+foreground = "#888888"
+
+[[block]]
+name = "clock"
+type = "text"
+inherit = "dim"
+value = "${greeting} ${clock:value|max:10}"
+show_if_matches = [["${clock:value}", "^\\d+"]]
+"##,
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn test_function() {
+        let problems = check_str(
+            "function",
+            r#"
+[[command]]
+name = "net"
+command = "cat /sys/class/net/eth0/statistics/rx_bytes"
+
+[[var]]
+name = "rx"
+function = "rate(net:value)"
+value = "${value|bytes}/s"
+
+[[var]]
+name = "missing"
+function = "avg(disk:value, 30s)"
+
+[[var]]
+name = "broken"
+function = "median(net:value)"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "13:12: variable ${disk:value} is not produced by any [[var]] or [[command]]",
+                "17:12: Unknown function: \"median\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clock() {
+        let problems = check_str(
+            "clock",
+            r#"[[bar]]
+blocks_left = ["clock"]
+
+[[clock]]
+format = "%H:%M"
+precision = "minute"
+
+[[clock]]
+name = "tokyo"
+timezone = "Asia/Tokyo"
+
+[[block]]
+name = "clock"
+type = "text"
+value = "${clock:value} ${tokyo:value}"
+"#,
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        let problems = check_str(
+            "clock_env",
+            r#"[[clock]]
+name = "env"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec!["2:8: clock name \"env\" is reserved for ${env:...}"]
+        );
+
+        let problems = check_str(
+            "clock_command",
+            r#"[[clock]]
+
+[[command]]
+name = "clock"
+command = "date"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec!["4:8: command name \"clock\" is used by a [[clock]] too"]
+        );
+    }
+
+    #[test]
+    fn test_problems() {
+        let problems = check_str(
+            "problems",
+            r#"[[bar]]
+blocks_left = ["clock", "missing"]
+
+[[block]]
+name = "clock"
+type = "text"
+inherit = "nope"
+value = "${clock:value} ${unknown}"
+show_if_matches = [["${clock:value}", "(unclosed"]]
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "2:25: unknown block \"missing\" in blocks_left",
+                "7:11: inherit refers to unknown default_block \"nope\"",
+                "8:9: variable ${clock:value} is not produced by any [[var]] or [[command]]",
+                "8:9: variable ${unknown} is not produced by any [[var]] or [[command]]",
+                "9:21: variable ${clock:value} is not produced by any [[var]] or [[command]]",
+                "9:39: invalid regex: regex parse error:\n    (unclosed\n    ^\nerror: unclosed group",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_popup_bar() {
+        let problems = check_str(
+            "popup",
+            r##"[[bar]]
+popup = true
+blocks_left = ["clock"]
+
+[[block]]
+name = "clock"
+type = "text"
+value = "12:00"
+popup = "block"
+foreground = "not-a-color"
+"##,
+        );
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert_eq!(
+            problems[0],
+            "3:16: block \"clock\" has popup = \"block\", but this popup bar is only shown by popup = \"bar\" blocks"
+        );
+        assert!(problems[1].starts_with("10:14: invalid color \"not-a-color\" for foreground"));
+    }
+
+    #[test]
+    fn test_repeat() {
+        let problems = check_str(
+            "repeat",
+            r#"[[bar]]
+blocks_left = ["tray", "broken"]
+
+[[command]]
+name = "sni"
+command = "oatbar-sni"
+
+[[block]]
+name = "tray"
+type = "repeat"
+prefix = "sni:sni."
+block = "tray_item"
+
+[[block]]
+name = "tray_item"
+type = "text"
+value = "${instance} ${item.full_text}"
+
+[[block]]
+name = "broken"
+type = "repeat"
+prefix = "sni:sni."
+block = "nope"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec!["23:9: repeat refers to unknown block \"nope\""]
+        );
+    }
+
+    #[test]
+    fn test_i3bar() {
+        let problems = check_str(
+            "i3bar",
+            r#"[[bar]]
+blocks_left = ["status", "broken"]
+
+[[command]]
+name = "i3status"
+command = "i3status"
+
+[[block]]
+name = "status"
+type = "i3bar"
+command = "i3status"
+
+[[block]]
+name = "broken"
+type = "i3bar"
+command = "i3status-rs"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec!["16:11: i3bar block refers to unknown command \"i3status-rs\""]
+        );
+    }
+
+    #[test]
+    fn test_themes() {
+        let problems = check_str(
+            "themes",
+            r##"active_theme = "night"
+
+[theme.dark]
+accent = "#fab387"
+
+[[block]]
+name = "clock"
+type = "text"
+value = "${theme} ${theme.accent} ${theme.missing}"
+"##,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "1:16: active_theme refers to unknown theme \"night\"",
+                "9:9: variable ${theme.missing} is not produced by any [[var]] or [[command]]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let problems = check_str(
+            "timeout",
+            r#"[[command]]
+name = "gpu_temp"
+command = "nvidia-smi"
+interval = 5
+timeout = 3
+
+[[command]]
+name = "sni"
+command = "oatbar-sni"
+format = "i3bar"
+timeout = 60
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "11:11: timeout kills i3bar commands that print nothing for so long, e.g. while nothing changes"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let problems = check_str(
+            "env",
+            r#"[[command]]
+name = "env"
+command = "env"
+
+[[block]]
+name = "host"
+type = "text"
+value = "${env:HOSTNAME|def:unknown} ${xdg:config}"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec!["2:8: command name \"env\" is reserved for ${env:...}"]
+        );
+    }
+}
@@ -79,6 +79,10 @@ enum Commands {
     /// Restart oatbar by querying its launch command, terminating it securely via IPC,
     /// and respawning it strictly detached.
     Restart {},
+    /// Reload the configuration file without restarting oatbar.
+    ///
+    /// Commands that did not change keep running and their variables are preserved.
+    Reload {},
     /// Dump the current bar state to an absolute SVG file path.
     DumpSvg {
         /// Absolute path to write the SVG file to (e.g. /tmp/bar.svg).
@@ -152,6 +156,7 @@ fn main() -> anyhow::Result<()> {
             Ok(Default::default())
         }
         Commands::DumpSvg { path, index } => client.send_command(ipc::Command::DumpSvg { path, index }),
+        Commands::Reload {} => client.send_command(ipc::Command::Reload {}),
     }?;
     if let Some(error) = response.error {
         return Err(anyhow!("{}", error));
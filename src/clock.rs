@@ -0,0 +1,275 @@
+// Copyright 2023 Oatbar Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Offset, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::{state, timer};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Precision {
+    #[default]
+    Second,
+    Minute,
+    Hour,
+}
+
+impl Precision {
+    fn seconds(self) -> i64 {
+        match self {
+            Precision::Second => 1,
+            Precision::Minute => 60,
+            Precision::Hour => 3600,
+        }
+    }
+}
+
+/// Built-in source of the current time, setting `${<name>:value}`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ClockConfig {
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// strftime format, e.g. `%H:%M`.
+    #[serde(default = "default_format")]
+    format: String,
+    /// IANA timezone, e.g. `Asia/Tokyo`. Local time if not set.
+    timezone: Option<String>,
+    /// How often the value changes, updates happen exactly at these wall-clock boundaries.
+    #[serde(default)]
+    precision: Precision,
+}
+
+fn default_name() -> String {
+    "clock".into()
+}
+
+fn default_format() -> String {
+    "%a %b %e %H:%M:%S".into()
+}
+
+pub struct Clock {
+    config: ClockConfig,
+    timezone: Option<chrono_tz::Tz>,
+}
+
+impl Clock {
+    pub fn new(config: ClockConfig) -> anyhow::Result<Self> {
+        if StrftimeItems::new(&config.format).any(|item| item == Item::Error) {
+            return Err(anyhow::anyhow!(
+                "clock {:?}: invalid format {:?}",
+                config.name,
+                config.format
+            ));
+        }
+        let timezone = match &config.timezone {
+            Some(timezone) => Some(timezone.parse().map_err(|e| {
+                anyhow::anyhow!("clock {:?}: unknown timezone: {}", config.name, e)
+            })?),
+            None => None,
+        };
+        Ok(Self { config, timezone })
+    }
+
+    /// Formats `now` and returns it with the UTC offset in seconds.
+    fn format_in<Tz>(&self, now: DateTime<Utc>, timezone: &Tz) -> (String, i64)
+    where
+        Tz: TimeZone,
+        Tz::Offset: Display,
+    {
+        let time = now.with_timezone(timezone);
+        let offset = time.offset().fix().local_minus_utc() as i64;
+        (time.format(&self.config.format).to_string(), offset)
+    }
+
+    fn format(&self, now: DateTime<Utc>) -> (String, i64) {
+        match &self.timezone {
+            Some(timezone) => self.format_in(now, timezone),
+            None => self.format_in(now, &Local),
+        }
+    }
+
+    /// The first `precision` boundary after `now` in the clock's timezone.
+    fn next_tick(&self, now: DateTime<Utc>, offset: i64) -> DateTime<Utc> {
+        let step = self.config.precision.seconds();
+        let local = now.timestamp() + offset;
+        let next = (local.div_euclid(step) + 1) * step - offset;
+        Utc.timestamp_opt(next, 0).single().unwrap_or(now)
+    }
+
+    fn var_update(&self, value: String) -> state::Update {
+        state::Update::VarUpdate(state::VarUpdate {
+            command_name: Some(self.config.name.clone()),
+            entries: vec![state::UpdateEntry {
+                var: "value".into(),
+                value,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    }
+
+    fn spawn(
+        self,
+        tx: crossbeam_channel::Sender<state::Update>,
+        stopped: Arc<AtomicBool>,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let (value, offset) = self.format(now);
+        tx.send(self.var_update(value))?;
+        let name = format!("clock-{}", self.config.name);
+        timer::Timer::repeating(&name, self.next_tick(now, offset).into(), move || {
+            if stopped.load(Ordering::SeqCst) {
+                return None;
+            }
+            let now = Utc::now();
+            let (value, offset) = self.format(now);
+            tx.send(self.var_update(value)).ok()?;
+            Some(self.next_tick(now, offset).into())
+        })?;
+        Ok(())
+    }
+}
+
+/// Clocks currently running, keyed by name.
+pub struct ClockSet {
+    tx: crossbeam_channel::Sender<state::Update>,
+    running: HashMap<String, (ClockConfig, Arc<AtomicBool>)>,
+}
+
+impl ClockSet {
+    pub fn new(tx: crossbeam_channel::Sender<state::Update>) -> Self {
+        Self {
+            tx,
+            running: HashMap::new(),
+        }
+    }
+
+    pub fn apply(&mut self, configs: Vec<ClockConfig>) -> anyhow::Result<()> {
+        let outdated: Vec<String> = self
+            .running
+            .iter()
+            .filter(|(name, (config, _))| !configs.iter().any(|c| c.name == **name && c == config))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in outdated {
+            if let Some((_, stopped)) = self.running.remove(&name) {
+                tracing::info!("Stopping clock {}", name);
+                stopped.store(true, Ordering::SeqCst);
+                self.tx.send(state::Update::VarUpdate(state::VarUpdate {
+                    command_name: Some(name),
+                    snapshot: true,
+                    ..Default::default()
+                }))?;
+            }
+        }
+
+        for config in configs {
+            if self.running.contains_key(&config.name) {
+                continue;
+            }
+            tracing::info!("Starting clock {}", config.name);
+            let clock = Clock::new(config.clone())?;
+            let stopped = Arc::new(AtomicBool::new(false));
+            clock.spawn(self.tx.clone(), stopped.clone())?;
+            self.running.insert(config.name.clone(), (config, stopped));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(precision: Precision, timezone: Option<&str>) -> Clock {
+        Clock::new(ClockConfig {
+            name: "clock".into(),
+            format: "%H:%M:%S".into(),
+            timezone: timezone.map(String::from),
+            precision,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_next_tick() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 10, 20, 30).unwrap();
+        let at = |h, m, s| Utc.with_ymd_and_hms(2024, 3, 5, h, m, s).unwrap();
+        assert_eq!(
+            clock(Precision::Second, None).next_tick(now, 0),
+            at(10, 20, 31)
+        );
+        assert_eq!(
+            clock(Precision::Minute, None).next_tick(now, 0),
+            at(10, 21, 0)
+        );
+        assert_eq!(clock(Precision::Hour, None).next_tick(now, 0), at(11, 0, 0));
+        // Hours start at half past in UTC+5:30.
+        assert_eq!(
+            clock(Precision::Hour, None).next_tick(now, 5 * 3600 + 1800),
+            at(10, 30, 0)
+        );
+    }
+
+    #[test]
+    fn test_timezone() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 10, 20, 30).unwrap();
+        assert_eq!(
+            clock(Precision::Second, Some("Asia/Tokyo")).format(now),
+            ("19:20:30".to_string(), 9 * 3600)
+        );
+        assert_eq!(
+            clock(Precision::Second, Some("UTC")).format(now),
+            ("10:20:30".to_string(), 0)
+        );
+        assert!(Clock::new(ClockConfig {
+            name: "clock".into(),
+            format: "%H:%M".into(),
+            timezone: Some("Mars/Olympus".into()),
+            precision: Precision::Minute,
+        })
+        .is_err());
+        assert!(Clock::new(ClockConfig {
+            name: "clock".into(),
+            format: "%H:%Q".into(),
+            timezone: None,
+            precision: Precision::Minute,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_apply_invalid() {
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        let mut clocks = ClockSet::new(tx);
+        assert!(clocks
+            .apply(vec![ClockConfig {
+                name: "clock".into(),
+                format: "%H:%M".into(),
+                timezone: Some("Mars/Olympus".into()),
+                precision: Precision::Minute,
+            }])
+            .is_err());
+        assert!(clocks.running.is_empty());
+    }
+}
@@ -12,22 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::clock;
 use crate::parse::{Placeholder, PlaceholderContext};
 use crate::popup_visibility;
 use crate::source;
 
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::Write;
 use std::marker::PhantomData;
-use std::path::Path;
-use std::{collections::HashMap, io::Read};
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use serde::{de, de::Deserializer, Deserialize};
 use tracing::{debug, warn};
 
 #[derive(Debug, Clone, Deserialize, Copy, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum PopupMode {
     Bar,
@@ -36,6 +38,7 @@ pub enum PopupMode {
 }
 
 #[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Decorations<Dynamic: Clone + Default + Debug> {
     pub foreground: Dynamic,
     pub background: Dynamic,
@@ -108,6 +111,7 @@ impl Decorations<Option<Placeholder>> {
 serde_with::with_prefix!(prefix_hover "hover_");
 
 #[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DisplayOptions<Dynamic: Clone + Default + Debug> {
     pub font: Dynamic,
     pub popup_value: Dynamic,
@@ -118,6 +122,7 @@ pub struct DisplayOptions<Dynamic: Clone + Default + Debug> {
     #[serde(flatten)]
     pub decorations: Decorations<Dynamic>,
     #[serde(flatten, with = "prefix_hover")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "hover_decorations_schema"))]
     pub hover_decorations: Decorations<Dynamic>,
     #[serde(default)]
     pub show_if_matches: Vec<(Dynamic, Regex)>,
@@ -190,13 +195,18 @@ impl DisplayOptions<Option<Placeholder>> {
             },
             popup_show_if_some: vec![],
             popup: self.popup.or(default.popup),
-            pango_markup: Some(self.pango_markup.unwrap_or(true)),
+            pango_markup: self.pango_markup.or(default.pango_markup).or(Some(true)),
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct Regex(#[serde(with = "serde_regex")] regex::Regex);
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Regex(
+    #[serde(with = "serde_regex")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    regex::Regex,
+);
 
 impl Regex {
     pub fn is_match(&self, haystack: &str) -> bool {
@@ -227,6 +237,7 @@ impl VecStringRegexEx for Vec<(Placeholder, Regex)> {
 }
 
 #[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Replace<Dynamic: Clone + Default + Debug>(Vec<(Regex, Dynamic)>);
 
 impl Replace<Placeholder> {
@@ -236,6 +247,12 @@ impl Replace<Placeholder> {
         }
         Ok(())
     }
+
+    fn needs_update(&self, vars: &dyn PlaceholderContext) -> bool {
+        self.0
+            .iter()
+            .any(|(_, replacement)| replacement.needs_update(vars))
+    }
 }
 
 impl Replace<Placeholder> {
@@ -259,6 +276,7 @@ impl Replace<Placeholder> {
 serde_with::with_prefix!(prefix_active "active_");
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct EventHandlers<Dynamic: Clone + Default + Debug> {
     pub on_mouse_left: Dynamic,
@@ -296,6 +314,7 @@ impl EventHandlers<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct EnumBlock<Dynamic: Clone + Default + Debug> {
     pub name: String,
@@ -307,6 +326,7 @@ pub struct EnumBlock<Dynamic: Clone + Default + Debug> {
     #[serde(flatten)]
     pub display: DisplayOptions<Dynamic>,
     #[serde(flatten, with = "prefix_active")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "active_display_schema"))]
     pub active_display: DisplayOptions<Dynamic>,
     pub enum_separator: Option<String>,
     #[serde(flatten)]
@@ -332,6 +352,7 @@ impl EnumBlock<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct TextBlock<Dynamic: Clone + Default + Debug> {
     pub name: String,
@@ -361,6 +382,7 @@ impl TextBlock<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum NumberType {
     Number,
@@ -418,6 +440,7 @@ where
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct TextProgressBarDisplay<Dynamic: Clone + Default + Debug> {
     // Known issue: RTL characters reverse the bar direction.
@@ -426,16 +449,19 @@ pub struct TextProgressBarDisplay<Dynamic: Clone + Default + Debug> {
     // fill: self.fill.unwrap_or_else(|| "\u{202D}ﭳ".into()),
     // indicator: self.indicator.unwrap_or_else(|| "\u{202D}ﭳ".into()),
     #[serde(default = "default_progress_fill", deserialize_with = "string_or_ramp")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "string_or_ramp_schema"))]
     pub fill: Vec<(String, String)>,
     #[serde(
         default = "default_progress_indicator",
         deserialize_with = "string_or_ramp"
     )]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "string_or_ramp_schema"))]
     pub indicator: Vec<(String, String)>,
     #[serde(
         default = "default_progress_empty",
         deserialize_with = "string_or_ramp"
     )]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "string_or_ramp_schema"))]
     pub empty: Vec<(String, String)>,
     #[serde(default = "default_progress_size")]
     pub progress_bar_size: usize,
@@ -472,6 +498,7 @@ impl TextProgressBarDisplay<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct NumberTextDisplay<Dynamic: Clone + Default + Debug> {
     pub number_type: Option<NumberType>,
@@ -490,6 +517,7 @@ impl NumberTextDisplay<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "number_display")]
 pub enum NumberDisplay<Dynamic: Clone + Default + Debug> {
@@ -507,6 +535,7 @@ pub struct NumberParsedData {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct NumberBlock<Dynamic: Clone + Default + Debug> {
     pub name: String,
@@ -571,6 +600,7 @@ impl NumberBlock<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[cfg(feature = "image")]
 pub struct ImageOptions {
@@ -578,6 +608,7 @@ pub struct ImageOptions {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[cfg(feature = "image")]
 pub struct ImageBlock<Dynamic: Clone + Default + Debug> {
@@ -621,7 +652,78 @@ impl ImageBlock<Option<Placeholder>> {
     }
 }
 
+/// Renders the `block` once per instance found under the `prefix` var.
+///
+/// For the var `stats:net.wlan0.ipv4_0_addr` and the prefix `stats:net.`,
+/// the instance is `wlan0`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub struct RepeatBlock<Dynamic: Clone + Default + Debug> {
+    pub name: String,
+    pub prefix: String,
+    pub block: String,
+    #[serde(skip)]
+    pub phantom_data: PhantomData<Dynamic>,
+}
+
+impl RepeatBlock<Option<Placeholder>> {
+    pub fn with_default(self) -> RepeatBlock<Placeholder> {
+        RepeatBlock {
+            name: self.name,
+            prefix: self.prefix,
+            block: self.block,
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<Dynamic: Clone + Default + Debug> RepeatBlock<Dynamic> {
+    /// Sorted instances present among `var_names`.
+    pub fn instances<'a>(&self, var_names: impl Iterator<Item = &'a str>) -> Vec<String> {
+        let instances: std::collections::BTreeSet<&str> = var_names
+            .filter_map(|var| var.strip_prefix(self.prefix.as_str()))
+            .filter_map(|rest| rest.rsplit_once('.'))
+            .map(|(instance, _)| instance)
+            .filter(|instance| !instance.is_empty())
+            .collect();
+        instances.into_iter().map(String::from).collect()
+    }
+}
+
+/// Shows every block of a command with i3bar output, styled by the command.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub struct I3barBlock<Dynamic: Clone + Default + Debug> {
+    pub name: String,
+    pub inherit: Option<String>,
+    /// Name of the command.
+    pub command: String,
+    /// Used for i3bar blocks that do not set their own colors.
+    #[serde(flatten)]
+    pub display: DisplayOptions<Dynamic>,
+    #[serde(flatten)]
+    pub event_handlers: EventHandlers<Dynamic>,
+}
+
+impl I3barBlock<Option<Placeholder>> {
+    pub fn with_default(
+        self,
+        default_block: &DefaultBlock<Placeholder>,
+    ) -> I3barBlock<Placeholder> {
+        I3barBlock {
+            name: self.name,
+            inherit: self.inherit,
+            command: self.command,
+            display: self.display.with_default(&default_block.display),
+            event_handlers: self.event_handlers.with_default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum SeparatorType {
     Left,
@@ -630,6 +732,7 @@ pub enum SeparatorType {
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum Block<Dynamic: Clone + Default + Debug> {
@@ -638,9 +741,23 @@ pub enum Block<Dynamic: Clone + Default + Debug> {
     Number(NumberBlock<Dynamic>),
     #[cfg(feature = "image")]
     Image(ImageBlock<Dynamic>),
+    Repeat(RepeatBlock<Dynamic>),
+    I3bar(I3barBlock<Dynamic>),
 }
 
 impl Block<Option<Placeholder>> {
+    pub fn name(&self) -> &str {
+        match self {
+            Block::Text(e) => &e.name,
+            Block::Enum(e) => &e.name,
+            Block::Number(e) => &e.name,
+            #[cfg(feature = "image")]
+            Block::Image(e) => &e.name,
+            Block::Repeat(e) => &e.name,
+            Block::I3bar(e) => &e.name,
+        }
+    }
+
     pub fn inherit(&self) -> &Option<String> {
         match self {
             Block::Text(e) => &e.inherit,
@@ -648,6 +765,8 @@ impl Block<Option<Placeholder>> {
             Block::Number(e) => &e.inherit,
             #[cfg(feature = "image")]
             Block::Image(e) => &e.inherit,
+            Block::Repeat(_) => &None,
+            Block::I3bar(e) => &e.inherit,
         }
     }
     pub fn with_default_and_name(
@@ -660,6 +779,8 @@ impl Block<Option<Placeholder>> {
             Block::Number(e) => (e.name.clone(), Block::Number(e.with_default(default_block))),
             #[cfg(feature = "image")]
             Block::Image(e) => (e.name.clone(), Block::Image(e.with_default(default_block))),
+            Block::Repeat(e) => (e.name.clone(), Block::Repeat(e.with_default())),
+            Block::I3bar(e) => (e.name.clone(), Block::I3bar(e.with_default(default_block))),
         }
     }
 }
@@ -672,9 +793,32 @@ impl Block<Placeholder> {
             Block::Number(e) => e.display.popup,
             #[cfg(feature = "image")]
             Block::Image(e) => e.display.popup,
+            Block::Repeat(_) => None,
+            Block::I3bar(e) => e.display.popup,
         }
     }
 
+    /// Vars deciding whether the block is visible.
+    pub fn visibility_vars(&self) -> Vec<&str> {
+        let display = match self {
+            Block::Text(e) => &e.display,
+            Block::Enum(e) => &e.display,
+            Block::Number(e) => &e.display,
+            #[cfg(feature = "image")]
+            Block::Image(e) => &e.display,
+            Block::Repeat(_) => return vec![],
+            Block::I3bar(e) => &e.display,
+        };
+        display
+            .show_if_matches
+            .iter()
+            .map(|(expr, _)| expr)
+            .chain(display.popup_show_if_some.iter())
+            .chain([&display.popup_value])
+            .flat_map(|placeholder| placeholder.var_names())
+            .collect()
+    }
+
     pub fn add_popup_var(&mut self, var: Placeholder) {
         match self {
             Block::Text(e) => e.display.popup_show_if_some.push(var),
@@ -682,11 +826,14 @@ impl Block<Placeholder> {
             Block::Number(e) => e.display.popup_show_if_some.push(var),
             #[cfg(feature = "image")]
             Block::Image(e) => e.display.popup_show_if_some.push(var),
+            Block::Repeat(_) => {}
+            Block::I3bar(e) => e.display.popup_show_if_some.push(var),
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum BarPosition {
     Top,
@@ -695,7 +842,8 @@ pub enum BarPosition {
     Bottom,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(default)]
 pub struct Margin {
     pub left: u16,
@@ -755,6 +903,7 @@ where
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(default)]
 pub struct Bar<Dynamic: Clone + Default + Debug> {
     #[serde(skip)]
@@ -768,6 +917,7 @@ pub struct Bar<Dynamic: Clone + Default + Debug> {
     #[serde(default = "default_bar_position")]
     pub position: BarPosition,
     #[serde(default = "default_margin", deserialize_with = "int_or_struct")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "margin_schema"))]
     pub margin: Margin,
     pub background: Dynamic,
     #[serde(default)]
@@ -780,6 +930,19 @@ pub struct Bar<Dynamic: Clone + Default + Debug> {
     pub popup_show_if_some: Vec<Dynamic>,
 }
 
+impl<Dynamic: Clone + Default + Debug> Bar<Dynamic> {
+    /// Whether the window of this bar can show `other` too, only the contents differ.
+    pub fn same_window(&self, other: &Self) -> bool {
+        self.index == other.index
+            && self.monitor == other.monitor
+            && self.height == other.height
+            && self.position == other.position
+            && self.margin == other.margin
+            && self.popup == other.popup
+            && self.popup_at_edge == other.popup_at_edge
+    }
+}
+
 impl Bar<Option<Placeholder>> {
     fn with_default(&self) -> Bar<Placeholder> {
         Bar {
@@ -793,7 +956,7 @@ impl Bar<Option<Placeholder>> {
             background: self
                 .background
                 .clone()
-                .unwrap_or_else(|| Placeholder::infallable("#191919")),
+                .unwrap_or_else(|| Placeholder::infallable("${theme.background|def:#191919}")),
             popup: self.popup || !self.show_if_matches.is_empty() || self.popup_at_edge,
             popup_at_edge: self.popup_at_edge,
             index: 0,
@@ -813,12 +976,16 @@ impl Bar<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(default)]
 pub struct DefaultBlock<Dynamic: Clone + Default + Debug> {
     pub name: Option<String>,
+    /// Named `default_block` to take the unset options from.
+    pub inherit: Option<String>,
     #[serde(flatten)]
     pub display: DisplayOptions<Dynamic>,
     #[serde(flatten, with = "prefix_active")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "active_display_schema"))]
     pub active_display: DisplayOptions<Dynamic>,
 }
 
@@ -826,6 +993,7 @@ impl DefaultBlock<Option<Placeholder>> {
     fn with_default(&self, default_block: &DefaultBlock<Placeholder>) -> DefaultBlock<Placeholder> {
         DefaultBlock {
             name: None,
+            inherit: None,
             display: self.display.clone().with_default(&default_block.display),
             active_display: self.active_display.clone().with_default(
                 &self
@@ -838,6 +1006,7 @@ impl DefaultBlock<Option<Placeholder>> {
 }
 
 #[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Input<Dynamic: Clone + Default + Debug> {
     pub value: Dynamic,
     #[serde(default)]
@@ -867,6 +1036,11 @@ impl Input<Option<Placeholder>> {
 
 impl Input<Placeholder> {
     pub fn update(&mut self, vars: &dyn PlaceholderContext) -> anyhow::Result<bool> {
+        if !self.value.needs_update(vars) && !self.replace.needs_update(vars) {
+            return Ok(false);
+        }
+        // The value holds the result of replacements, it has to be resolved again.
+        self.value.invalidate();
         let old_value = self.value.value.clone();
         self.value.update(vars)?;
         self.replace.update(vars)?;
@@ -878,10 +1052,15 @@ impl Input<Placeholder> {
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Var<Dynamic: Clone + Default + Debug> {
     pub name: String,
     #[serde(flatten)]
     pub input: Input<Dynamic>,
+    /// Derives `${value}` from other vars as their updates arrive.
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub function: Option<VarFunction>,
 }
 
 impl Var<Option<Placeholder>> {
@@ -889,12 +1068,92 @@ impl Var<Option<Placeholder>> {
         Var {
             name: self.name.clone(),
             input: self.input.with_defaults(),
+            function: self.function.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum VarFunction {
+    /// Change of a counter per second.
+    Rate(String),
+    Avg(String, std::time::Duration),
+    Min(String, std::time::Duration),
+    Max(String, std::time::Duration),
+    /// Sum of all vars with names matching a glob.
+    Sum(Regex),
+}
+
+impl VarFunction {
+    pub fn parse(expression: &str) -> anyhow::Result<Self> {
+        let (name, args) = expression
+            .trim()
+            .strip_suffix(')')
+            .and_then(|e| e.split_once('('))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Function format must be name(args...), found: {:?}",
+                    expression
+                )
+            })?;
+        let args: Vec<&str> = args.split(',').map(str::trim).collect();
+        if args.iter().any(|arg| arg.is_empty()) {
+            return Err(anyhow::anyhow!("Empty argument in {:?}", expression));
+        }
+        let window = |window: &str| {
+            serde_ext_duration::parse_str(window)
+                .map_err(|e| anyhow::anyhow!("Invalid window {:?}: {}", window, e))
+        };
+        match (name.trim(), args.as_slice()) {
+            ("rate", [var]) => Ok(Self::Rate(var.to_string())),
+            ("avg", [var, w]) => Ok(Self::Avg(var.to_string(), window(w)?)),
+            ("min", [var, w]) => Ok(Self::Min(var.to_string(), window(w)?)),
+            ("max", [var, w]) => Ok(Self::Max(var.to_string(), window(w)?)),
+            ("sum", [glob]) => {
+                let pattern = regex::escape(glob).replace("\\*", ".*");
+                Ok(Self::Sum(Regex(regex::Regex::new(&format!(
+                    "^{}$",
+                    pattern
+                ))?)))
+            }
+            ("rate" | "avg" | "min" | "max" | "sum", _) => Err(anyhow::anyhow!(
+                "Wrong number of arguments in {:?}, expected rate(var), sum(glob) or {}(var, window)",
+                expression,
+                name.trim()
+            )),
+            (name, _) => Err(anyhow::anyhow!("Unknown function: {:?}", name)),
         }
     }
+
+    /// The var sampled by the function, all functions except `sum` have one.
+    pub fn var(&self) -> Option<&str> {
+        match self {
+            Self::Rate(var) | Self::Avg(var, _) | Self::Min(var, _) | Self::Max(var, _) => {
+                Some(var)
+            }
+            Self::Sum(_) => None,
+        }
+    }
+}
+
+impl TryFrom<String> for VarFunction {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Config<Dynamic: Clone + Default + Debug> {
+    /// Other config files merged into this one, glob patterns relative to this file.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Files and directories the config was loaded from.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+    #[serde(default)]
     pub bar: Vec<Bar<Dynamic>>,
     #[serde(skip)]
     pub blocks: HashMap<String, Block<Dynamic>>,
@@ -903,34 +1162,76 @@ pub struct Config<Dynamic: Clone + Default + Debug> {
     #[serde(skip)]
     pub var_order: Vec<String>,
     #[serde(default, rename = "block")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "blocks_schema"))]
     pub blocks_vec: Vec<Block<Dynamic>>,
     #[serde(default, rename = "var")]
     pub vars_vec: Vec<Var<Dynamic>>,
     #[serde(default, rename = "command")]
     pub commands: Vec<source::CommandConfig>,
+    #[serde(default, rename = "clock")]
+    pub clocks: Vec<clock::ClockConfig>,
     #[serde(default, rename = "default_block")]
     pub default_block_vec: Vec<DefaultBlock<Dynamic>>,
+    /// Blocks parameterized with `${param.<name>}`, used by blocks with `template`.
+    #[serde(default, rename = "block_template")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Vec<serde_json::Map<String, serde_json::Value>>")
+    )]
+    pub block_templates: Vec<toml::Table>,
+    /// Named sets of colors and fonts, available as `${theme.<key>}` vars.
+    #[serde(default, rename = "theme")]
+    pub themes: BTreeMap<String, Theme>,
+    /// The theme used until the `theme` var selects another one.
+    #[serde(default)]
+    pub active_theme: Option<String>,
+    /// Saves vars to restore them after a restart.
+    #[serde(default)]
+    pub persist: bool,
+    /// Regexes of the names of saved vars, all vars are saved if empty. Implies `persist`.
+    #[serde(default)]
+    pub persist_vars: Vec<Regex>,
 }
 
+pub type Theme = BTreeMap<String, String>;
+
+/// The var selecting the active theme.
+pub const THEME_VAR: &str = "theme";
+
 impl Config<Option<Placeholder>> {
-    fn with_defaults(&self) -> Config<Placeholder> {
+    fn with_defaults(&self) -> anyhow::Result<Config<Placeholder>> {
         let base_default_block = DefaultBlock {
             name: None,
+            inherit: None,
             display: default_display(),
             active_display: default_active_display(),
         };
-        let none_default_block = self
-            .default_block_vec
-            .iter()
-            .find(|b| b.name.is_none())
-            .map(|b| b.with_default(&base_default_block))
-            .unwrap_or_else(|| base_default_block);
-        let mut default_block_map: HashMap<Option<String>, DefaultBlock<Placeholder>> = self
+        let none_default_block = match self.default_block_vec.iter().find(|b| b.name.is_none()) {
+            Some(b) if b.inherit.is_some() => {
+                return Err(anyhow::anyhow!(
+                    "default_block without a name can not inherit {:?}",
+                    b.inherit.as_deref().unwrap_or_default()
+                ))
+            }
+            Some(b) => b.with_default(&base_default_block),
+            None => base_default_block,
+        };
+        let named_default_blocks: HashMap<&str, &DefaultBlock<Option<Placeholder>>> = self
             .default_block_vec
             .iter()
-            .map(|b| (b.name.clone(), b.clone().with_default(&none_default_block)))
+            .filter_map(|b| Some((b.name.as_deref()?, b)))
             .collect();
+        let mut default_block_map: HashMap<Option<String>, DefaultBlock<Placeholder>> =
+            HashMap::new();
         default_block_map.insert(None, none_default_block.clone());
+        for name in named_default_blocks.keys() {
+            resolve_default_block(
+                name,
+                &named_default_blocks,
+                &mut default_block_map,
+                &mut vec![],
+            )?;
+        }
         let blocks: HashMap<String, Block<Placeholder>> = self
             .blocks_vec
             .iter()
@@ -942,7 +1243,7 @@ impl Config<Option<Placeholder>> {
                 )
             })
             .collect();
-        Config {
+        Ok(Config {
             bar: self
                 .bar
                 .iter()
@@ -964,10 +1265,492 @@ impl Config<Option<Placeholder>> {
             vars_vec: vec![],
             default_block_vec: vec![],
             commands: self.commands.clone(),
+            clocks: self.clocks.clone(),
+            include: vec![],
+            block_templates: vec![],
+            themes: self.themes.clone(),
+            active_theme: self.active_theme.clone(),
+            persist: self.persist,
+            persist_vars: self.persist_vars.clone(),
+            sources: self.sources.clone(),
+        })
+    }
+}
+
+/// Resolves the named `default_block` and the chain of default blocks it inherits.
+fn resolve_default_block<'a>(
+    name: &'a str,
+    named_default_blocks: &HashMap<&'a str, &'a DefaultBlock<Option<Placeholder>>>,
+    resolved: &mut HashMap<Option<String>, DefaultBlock<Placeholder>>,
+    chain: &mut Vec<&'a str>,
+) -> anyhow::Result<DefaultBlock<Placeholder>> {
+    if let Some(default_block) = resolved.get(&Some(name.to_string())) {
+        return Ok(default_block.clone());
+    }
+    if chain.contains(&name) {
+        return Err(anyhow::anyhow!(
+            "default_block inheritance cycle: {} -> {}",
+            chain.join(" -> "),
+            name
+        ));
+    }
+    let default_block = named_default_blocks[name];
+    let parent = match &default_block.inherit {
+        Some(parent) if !named_default_blocks.contains_key(parent.as_str()) => {
+            return Err(anyhow::anyhow!(
+                "default_block {:?} inherits unknown default_block {:?}",
+                name,
+                parent
+            ))
+        }
+        Some(parent) => {
+            chain.push(name);
+            let parent = resolve_default_block(parent, named_default_blocks, resolved, chain)?;
+            chain.pop();
+            parent
+        }
+        None => resolved[&None].clone(),
+    };
+    let mut default_block = default_block.with_default(&parent);
+    default_block.name = Some(name.to_string());
+    resolved.insert(Some(name.to_string()), default_block.clone());
+    Ok(default_block)
+}
+
+/// Merges config files pulled in by `include`, remembering where names came from.
+#[derive(Default)]
+struct Loader {
+    config: Config<Option<Placeholder>>,
+    visited: HashSet<PathBuf>,
+    block_files: HashMap<String, PathBuf>,
+    var_files: HashMap<String, PathBuf>,
+    template_files: HashMap<String, PathBuf>,
+    theme_files: HashMap<String, PathBuf>,
+    /// Blocks with `template`, instantiated once all templates are loaded.
+    template_blocks: Vec<TemplateBlock>,
+}
+
+/// A block with `template` and where it is defined.
+struct TemplateBlock {
+    table: toml::Table,
+    path: PathBuf,
+    /// 1-based line and column.
+    location: (usize, usize),
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path =
+            std::fs::canonicalize(path).with_context(|| format!("unable to open {:?}", path))?;
+        if !self.visited.insert(path.clone()) {
+            return Ok(());
+        }
+        self.config.sources.push(path.clone());
+
+        let data =
+            std::fs::read_to_string(&path).with_context(|| format!("unable to read {:?}", path))?;
+        // Deserializing the spanned table keeps the locations in the error messages.
+        let with_input = |mut e: toml::de::Error| {
+            e.set_input(Some(data.as_str()));
+            e
+        };
+        let mut root = toml::de::DeTable::parse(&data)
+            .map_err(with_input)
+            .with_context(|| format!("unable to parse {:?}", path))?;
+        for table in take_template_blocks(root.get_mut()) {
+            let span = table.span();
+            let table = toml::Table::deserialize(toml::de::ValueDeserializer::from(table))
+                .map_err(with_input)
+                .with_context(|| format!("unable to parse {:?}", path))?;
+            self.template_blocks.push(TemplateBlock {
+                table,
+                path: path.clone(),
+                location: location(&data, span.start),
+            });
+        }
+        let config = Config::<Option<Placeholder>>::deserialize(toml::de::Deserializer::from(root))
+            .map_err(with_input)
+            .with_context(|| format!("unable to parse {:?}", path))?;
+
+        let base_dir = path.parent().unwrap_or(Path::new("/"));
+        for pattern in config.include.iter() {
+            let files = expand_glob(base_dir, pattern, &mut self.config.sources)
+                .with_context(|| format!("include {:?} in {:?}", pattern, path))?;
+            for file in files {
+                self.load_file(&file)
+                    .with_context(|| format!("included from {:?}", path))?;
+            }
+        }
+        self.merge(&path, config)
+    }
+
+    fn merge(&mut self, path: &Path, config: Config<Option<Placeholder>>) -> anyhow::Result<()> {
+        for block in config.blocks_vec.iter() {
+            check_duplicate(&mut self.block_files, "block", block.name(), path)?;
+        }
+        for var in config.vars_vec.iter() {
+            check_duplicate(&mut self.var_files, "var", &var.name, path)?;
+        }
+        for template in config.block_templates.iter() {
+            let name = template
+                .get("name")
+                .and_then(|name| name.as_str())
+                .context("block_template without a name")?;
+            check_duplicate(&mut self.template_files, "block_template", name, path)?;
+        }
+        for theme in config.themes.keys() {
+            check_duplicate(&mut self.theme_files, "theme", theme, path)?;
+        }
+        // Both set `<name>:value`, so they would overwrite each other.
+        let command_names: HashSet<String> = self
+            .config
+            .commands
+            .iter()
+            .chain(config.commands.iter())
+            .enumerate()
+            .map(|(index, command)| command.name(index))
+            .collect();
+        if let Some(clock) = self
+            .config
+            .clocks
+            .iter()
+            .chain(config.clocks.iter())
+            .find(|clock| command_names.contains(&clock.name))
+        {
+            return Err(anyhow::anyhow!(
+                "clock {:?} has the same name as a command, in {:?}",
+                clock.name,
+                path
+            ));
+        }
+        self.config.bar.extend(config.bar);
+        self.config.blocks_vec.extend(config.blocks_vec);
+        self.config.vars_vec.extend(config.vars_vec);
+        self.config.commands.extend(config.commands);
+        self.config.clocks.extend(config.clocks);
+        self.config
+            .default_block_vec
+            .extend(config.default_block_vec);
+        self.config.block_templates.extend(config.block_templates);
+        self.config.themes.extend(config.themes);
+        if config.active_theme.is_some() {
+            self.config.active_theme = config.active_theme;
+        }
+        self.config.persist |= config.persist;
+        self.config.persist_vars.extend(config.persist_vars);
+        Ok(())
+    }
+
+    fn instantiate_templates(&mut self) -> anyhow::Result<()> {
+        let templates: HashMap<&str, &toml::Table> = self
+            .config
+            .block_templates
+            .iter()
+            .filter_map(|t| Some((t.get("name")?.as_str()?, t)))
+            .collect();
+        let mut blocks = vec![];
+        for template_block in std::mem::take(&mut self.template_blocks) {
+            let TemplateBlock {
+                table,
+                path,
+                location: (line, column),
+            } = template_block;
+            let name = table
+                .get("name")
+                .and_then(|name| name.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let block = instantiate_template(table, &templates).with_context(|| {
+                format!(
+                    "block {:?} in {:?} at line {}, column {}",
+                    name, path, line, column
+                )
+            })?;
+            check_duplicate(&mut self.block_files, "block", block.name(), &path)?;
+            blocks.push(block);
+        }
+        self.config.blocks_vec.extend(blocks);
+        Ok(())
+    }
+}
+
+/// Removes blocks with `template` from a parsed config file.
+fn take_template_blocks<'i>(
+    table: &mut toml::de::DeTable<'i>,
+) -> Vec<toml::Spanned<toml::de::DeValue<'i>>> {
+    use toml::de::{DeArray, DeValue};
+    let Some(DeValue::Array(blocks)) = table.get_mut("block").map(toml::Spanned::get_mut) else {
+        return vec![];
+    };
+    let (template_blocks, other_blocks): (Vec<_>, Vec<_>) =
+        std::mem::replace(blocks, DeArray::new())
+            .into_iter()
+            .partition(|b| {
+                b.get_ref()
+                    .as_table()
+                    .is_some_and(|t| t.get("template").is_some())
+            });
+    *blocks = other_blocks.into_iter().collect();
+    template_blocks
+}
+
+/// 1-based line and column of the byte `offset` in `text`.
+pub fn location(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+fn param_regex() -> regex::Regex {
+    regex::Regex::new(r"\$\{param\.([A-Za-z0-9_-]+)\}").expect("valid regex")
+}
+
+fn for_each_string(value: &mut toml::Value, f: &mut impl FnMut(&mut String)) {
+    match value {
+        toml::Value::String(s) => f(s),
+        toml::Value::Array(items) => items.iter_mut().for_each(|v| for_each_string(v, f)),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| for_each_string(v, f)),
+        _ => {}
+    }
+}
+
+fn param_values(params: Option<toml::Value>) -> anyhow::Result<HashMap<String, String>> {
+    match params {
+        None => Ok(HashMap::new()),
+        Some(toml::Value::Table(params)) => Ok(params
+            .into_iter()
+            .map(|(name, value)| match value {
+                toml::Value::String(s) => (name, s),
+                other => (name, other.to_string()),
+            })
+            .collect()),
+        Some(_) => Err(anyhow::anyhow!("params must be a table")),
+    }
+}
+
+/// Expands a block with `template = "<name>"`, substituting `${param.<name>}` in the
+/// template with the block's `params` or the template's defaults.
+fn instantiate_template(
+    mut block: toml::Table,
+    templates: &HashMap<&str, &toml::Table>,
+) -> anyhow::Result<Block<Option<Placeholder>>> {
+    let template_name = match block.remove("template") {
+        Some(toml::Value::String(name)) => name,
+        _ => return Err(anyhow::anyhow!("template must be a string")),
+    };
+    let mut table = (*templates
+        .get(template_name.as_str())
+        .with_context(|| format!("unknown block_template {:?}", template_name))?)
+    .clone();
+    table.remove("name");
+    let mut params = param_values(table.remove("params"))?;
+    let overrides = param_values(block.remove("params"))?;
+    table.extend(block);
+
+    let re = param_regex();
+    let mut table = toml::Value::Table(table);
+    let mut used = HashSet::new();
+    for_each_string(&mut table, &mut |s| {
+        used.extend(re.captures_iter(s).map(|c| c[1].to_string()));
+    });
+    for name in overrides.keys() {
+        if !params.contains_key(name) && !used.contains(name) {
+            return Err(anyhow::anyhow!(
+                "unknown parameter {:?} for block_template {:?}",
+                name,
+                template_name
+            ));
+        }
+    }
+    params.extend(overrides);
+    if let Some(missing) = used.iter().find(|name| !params.contains_key(*name)) {
+        return Err(anyhow::anyhow!(
+            "missing parameter {:?} for block_template {:?}",
+            missing,
+            template_name
+        ));
+    }
+    for_each_string(&mut table, &mut |s| {
+        if let Cow::Owned(replaced) = re.replace_all(s, |c: &regex::Captures| params[&c[1]].clone())
+        {
+            *s = replaced;
+        }
+    });
+    Ok(Block::deserialize(table)?)
+}
+
+fn check_duplicate(
+    files: &mut HashMap<String, PathBuf>,
+    kind: &str,
+    name: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    match files.get(name) {
+        Some(other_path) if other_path != path => Err(anyhow::anyhow!(
+            "Duplicate {} {:?}: defined in {:?} and {:?}",
+            kind,
+            name,
+            other_path,
+            path
+        )),
+        _ => {
+            files.insert(name.to_string(), path.to_path_buf());
+            Ok(())
         }
     }
 }
 
+fn glob_regex(pattern: &str) -> anyhow::Result<regex::Regex> {
+    let mut re = String::from("^");
+    for ch in pattern.chars() {
+        match ch {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            ch => re.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    re.push('$');
+    Ok(regex::Regex::new(&re)?)
+}
+
+/// Expands `*` and `?` in the path components of `pattern`.
+///
+/// Directories that were searched are added to `sources`, so new matching
+/// files can be noticed later.
+pub fn expand_glob(
+    base_dir: &Path,
+    pattern: &str,
+    sources: &mut Vec<PathBuf>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let pattern = match pattern.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().context("Missing home dir")?.join(rest),
+        None => PathBuf::from(pattern),
+    };
+    let pattern = base_dir.join(pattern);
+    let mut paths = vec![PathBuf::new()];
+    let mut has_wildcards = false;
+    for component in pattern.components() {
+        let component = component.as_os_str().to_string_lossy();
+        if !component.contains(['*', '?']) {
+            for path in paths.iter_mut() {
+                path.push(component.as_ref());
+            }
+            continue;
+        }
+        has_wildcards = true;
+        let re = glob_regex(&component)?;
+        let mut matched = vec![];
+        for dir in paths {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            sources.push(dir.clone());
+            let mut names: Vec<String> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| !name.starts_with('.') && re.is_match(name))
+                .collect();
+            names.sort();
+            matched.extend(names.into_iter().map(|name| dir.join(name)));
+        }
+        paths = matched;
+    }
+    if !has_wildcards && !paths.iter().all(|p| p.exists()) {
+        return Err(anyhow::anyhow!("File {:?} not found", pattern));
+    }
+    Ok(paths)
+}
+
+/// Schema of a struct flattened with a `serde_with::with_prefix!` prefix.
+#[cfg(feature = "schema")]
+fn prefixed_schema<T: schemars::JsonSchema>(
+    generator: &mut schemars::SchemaGenerator,
+    prefix: &str,
+) -> schemars::Schema {
+    use serde_json::Value;
+    let mut schema = T::json_schema(generator);
+    if let Some(Value::Object(properties)) = schema.remove("properties") {
+        let properties = properties
+            .into_iter()
+            .map(|(name, property)| (format!("{}{}", prefix, name), property))
+            .collect();
+        schema.insert("properties".into(), Value::Object(properties));
+    }
+    if let Some(Value::Array(required)) = schema.remove("required") {
+        let required = required
+            .into_iter()
+            .filter_map(|name| name.as_str().map(|name| format!("{}{}", prefix, name)))
+            .map(Value::String)
+            .collect();
+        schema.insert("required".into(), Value::Array(required));
+    }
+    schema
+}
+
+#[cfg(feature = "schema")]
+fn hover_decorations_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    prefixed_schema::<Decorations<Option<Placeholder>>>(generator, "hover_")
+}
+
+#[cfg(feature = "schema")]
+fn active_display_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    prefixed_schema::<DisplayOptions<Option<Placeholder>>>(generator, "active_")
+}
+
+#[cfg(feature = "schema")]
+fn string_or_ramp_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "anyOf": [
+            { "type": "string" },
+            generator.subschema_for::<Vec<(String, String)>>(),
+        ]
+    })
+}
+
+#[cfg(feature = "schema")]
+fn margin_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "anyOf": [
+            { "type": "integer", "minimum": 0 },
+            generator.subschema_for::<Margin>(),
+        ]
+    })
+}
+
+/// A block instantiated from a `[[block_template]]`, only used for the schema.
+#[cfg(feature = "schema")]
+#[derive(schemars::JsonSchema)]
+#[schemars(rename = "TemplateBlock")]
+#[allow(dead_code)]
+struct TemplateBlockSchema {
+    name: String,
+    template: String,
+    #[serde(default)]
+    params: HashMap<String, String>,
+}
+
+#[cfg(feature = "schema")]
+fn blocks_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "type": "array",
+        "items": {
+            "anyOf": [
+                generator.subschema_for::<Block<Option<Placeholder>>>(),
+                generator.subschema_for::<TemplateBlockSchema>(),
+            ]
+        }
+    })
+}
+
+/// JSON Schema of the config file, for editors and TOML language servers.
+#[cfg(feature = "schema")]
+pub fn json_schema() -> schemars::Schema {
+    schemars::generate::SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Config<Option<Placeholder>>>()
+}
+
 fn default_bar_position() -> BarPosition {
     BarPosition::Bottom
 }
@@ -982,8 +1765,8 @@ fn default_margin() -> Margin {
 
 pub fn default_display() -> DisplayOptions<Placeholder> {
     let decorations = Decorations {
-        foreground: Placeholder::infallable("#dddddd"),
-        background: Placeholder::infallable("#191919"),
+        foreground: Placeholder::infallable("${theme.foreground|def:#dddddd}"),
+        background: Placeholder::infallable("${theme.background|def:#191919}"),
         overline_color: Placeholder::infallable(""),
         underline_color: Placeholder::infallable(""),
         edgeline_color: Placeholder::infallable(""),
@@ -992,7 +1775,7 @@ pub fn default_display() -> DisplayOptions<Placeholder> {
     DisplayOptions {
         popup_value: Placeholder::infallable(""),
         output_format: Placeholder::infallable("${value}"),
-        font: Placeholder::infallable("monospace 12"),
+        font: Placeholder::infallable("${theme.font|def:monospace 12}"),
         pango_markup: Some(true),
         margin: Some(0.0),
         padding: Some(8.0),
@@ -1011,7 +1794,7 @@ pub fn default_error_display() -> DisplayOptions<Placeholder> {
 fn default_active_display() -> DisplayOptions<Placeholder> {
     let default = default_display();
     let decorations = Decorations {
-        foreground: Placeholder::infallable("#ffffff"),
+        foreground: Placeholder::infallable("${theme.active_foreground|def:#ffffff}"),
         ..default.decorations
     };
     DisplayOptions {
@@ -1036,9 +1819,36 @@ pub fn write_default_config(config_path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn load() -> anyhow::Result<Config<Placeholder>> {
+/// Path of the config file, writing the default config if there is none.
+pub fn default_path(instance_name: Option<&str>) -> anyhow::Result<PathBuf> {
+    if let Some(path) = find_path(instance_name)? {
+        return Ok(path);
+    }
+    let path = dirs::config_dir()
+        .context("Missing config dir")?
+        .join("oatbar")
+        .join("config.toml");
+    warn!("Config at {:?} is missing. Writing default config...", path);
+    write_default_config(&path)?;
+    Ok(path)
+}
+
+/// Path of the existing config file, if any.
+///
+/// The `oatbar/<instance_name>.toml` of an explicitly named instance takes precedence over
+/// `oatbar/config.toml`.
+pub fn find_path(instance_name: Option<&str>) -> anyhow::Result<Option<PathBuf>> {
     let config_dir = dirs::config_dir().context("Missing config dir")?;
 
+    if let Some(instance_name) = instance_name {
+        let mut instance_path = config_dir.clone();
+        instance_path.push("oatbar");
+        instance_path.push(format!("{}.toml", instance_name));
+        if instance_path.exists() {
+            return Ok(Some(instance_path));
+        }
+    }
+
     let mut path = config_dir.clone();
     path.push("oatbar");
     path.push("config.toml");
@@ -1046,27 +1856,37 @@ pub fn load() -> anyhow::Result<Config<Placeholder>> {
     let mut legacy_path = config_dir.clone();
     legacy_path.push("oatbar.toml");
 
-    let final_path = if path.exists() {
-        path
+    if path.exists() {
+        Ok(Some(path))
     } else if legacy_path.exists() {
         warn!(
             "Using legacy config path {:?}. Consider moving it to {:?}",
             legacy_path, path
         );
-        legacy_path
+        Ok(Some(legacy_path))
     } else {
-        warn!("Config at {:?} is missing. Writing default config...", path);
-        write_default_config(&path)?;
-        path
-    };
+        Ok(None)
+    }
+}
 
-    let mut file =
-        std::fs::File::open(&final_path).context(format!("unable to open {:?}", &final_path))?;
-    let mut data = String::new();
-    file.read_to_string(&mut data)?;
+pub fn load_from(path: &Path) -> anyhow::Result<Config<Placeholder>> {
+    let mut loader = Loader::default();
+    loader.load_file(path)?;
+    loader.instantiate_templates()?;
+    if let Some(theme) = &loader.config.active_theme {
+        if !loader.config.themes.contains_key(theme) {
+            return Err(anyhow::anyhow!("active_theme {:?} is not defined", theme));
+        }
+    }
+    let mut clock_names = HashSet::new();
+    for clock in loader.config.clocks.iter() {
+        if !clock_names.insert(&clock.name) {
+            return Err(anyhow::anyhow!("clock {:?} is defined twice", clock.name));
+        }
+        clock::Clock::new(clock.clone())?;
+    }
 
-    let config: Config<Option<Placeholder>> = toml::from_str(&data)?;
-    let mut resolved_config = config.with_defaults();
+    let mut resolved_config = loader.config.with_defaults()?;
     debug!("Parsed config:\n{:#?}", resolved_config);
 
     popup_visibility::process_config(&mut resolved_config);
@@ -1078,6 +1898,7 @@ pub fn load() -> anyhow::Result<Config<Placeholder>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::write_files;
 
     #[test]
     fn test_default_config_parses() {
@@ -1112,6 +1933,414 @@ mod tests {
         assert_eq!(block.display.font.value, "b hello c");
     }
 
+    #[test]
+    fn test_input_replace_update() {
+        let input: Input<Option<Placeholder>> =
+            toml::from_str("value = \"${x}\"\nreplace = [[\"(.+)\", \"<$1>\"]]\n").unwrap();
+        let mut input = input.with_defaults();
+        let mut map = HashMap::new();
+        map.insert("x".to_string(), "a".to_string());
+        assert!(input.update(&map).unwrap());
+        assert_eq!(input.value.value, "<a>");
+
+        // Replacements are not applied again to the already replaced value.
+        let nothing_changed = |_: &str| false;
+        let context = crate::parse::PlaceholderContextWithChanges {
+            vars: &map,
+            changed: &nothing_changed,
+        };
+        assert!(!input.update(&context).unwrap());
+        assert_eq!(input.value.value, "<a>");
+        assert!(!input.update(&map).unwrap());
+        assert_eq!(input.value.value, "<a>");
+    }
+
+    #[test]
+    fn test_visibility_vars() {
+        let block: Block<Option<Placeholder>> = toml::from_str(
+            r#"
+type = "text"
+name = "player"
+value = "${player:title}"
+show_if_matches = [["${player:status}", "Playing"]]
+popup = "block"
+popup_value = "${player:title} ${volume:level}"
+"#,
+        )
+        .unwrap();
+        let (_, block) = block.with_default_and_name(&Default::default());
+        let mut vars = block.visibility_vars();
+        vars.sort();
+        assert_eq!(vars, vec!["player:status", "player:title", "volume:level"]);
+    }
+
+    #[test]
+    fn test_bar_same_window() {
+        let bar = |text: &str| toml::from_str::<Bar<Option<Placeholder>>>(text).unwrap();
+        let old = bar("blocks_left = [\"a\"]\nheight = 20");
+        assert!(old.same_window(&bar("blocks_left = [\"a\", \"b\"]\nheight = 20")));
+        assert!(old.same_window(&bar("background = \"#000000\"\nheight = 20")));
+        assert!(!old.same_window(&bar("blocks_left = [\"a\"]\nheight = 24")));
+        assert!(!old.same_window(&bar("height = 20\nmonitor = \"DP-1\"")));
+        assert!(!old.same_window(&bar("height = 20\nposition = \"top\"")));
+        assert!(!old.same_window(&bar("height = 20\npopup = true")));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = write_files(
+            "include",
+            &[
+                (
+                    "config.toml",
+                    "include = [\"blocks/*.toml\"]\n[[bar]]\nblocks_left=[\"a\", \"b\"]\n",
+                ),
+                (
+                    "blocks/a.toml",
+                    "[[var]]\nname=\"x\"\n[[block]]\nname=\"a\"\ntype=\"text\"\nvalue=\"${x}\"\n",
+                ),
+                ("blocks/b.toml", "[[block]]\nname=\"b\"\ntype=\"text\"\n"),
+                ("blocks/c.txt", "not toml"),
+            ],
+        );
+        let config = load_from(&dir.join("config.toml")).unwrap();
+        assert_eq!(config.bar.len(), 1);
+        assert!(config.blocks.contains_key("a"));
+        assert!(config.blocks.contains_key("b"));
+        assert_eq!(config.var_order, vec!["x"]);
+        assert!(config
+            .sources
+            .contains(&dir.join("blocks").canonicalize().unwrap()));
+    }
+
+    #[test]
+    fn test_block_template() {
+        let dir = write_files(
+            "template",
+            &[
+                (
+                    "config.toml",
+                    r#"include = ["templates.toml"]
+[[block]]
+name = "wifi"
+template = "net"
+params = { iface = "wlan0" }
+
+[[block]]
+name = "ethernet"
+template = "net"
+params = { iface = "eth0", label = "eth" }
+on_mouse_left = "nm-connection-editor"
+"#,
+                ),
+                (
+                    "templates.toml",
+                    r#"[[block_template]]
+name = "net"
+params = { label = "net" }
+type = "text"
+value = "${param.label}: ${net:${param.iface}.ipv4}"
+show_if_matches = [["${net:${param.iface}.up}", "true"]]
+"#,
+                ),
+            ],
+        );
+        let config = load_from(&dir.join("config.toml")).unwrap();
+        let Some(Block::Text(wifi)) = config.blocks.get("wifi") else {
+            panic!("wifi is not a text block");
+        };
+        assert_eq!(
+            wifi.input.value,
+            Placeholder::new("net: ${net:wlan0.ipv4}").unwrap()
+        );
+        assert_eq!(
+            wifi.display.show_if_matches[0].0,
+            Placeholder::new("${net:wlan0.up}").unwrap()
+        );
+        let Some(Block::Text(ethernet)) = config.blocks.get("ethernet") else {
+            panic!("ethernet is not a text block");
+        };
+        assert_eq!(
+            ethernet.input.value,
+            Placeholder::new("eth: ${net:eth0.ipv4}").unwrap()
+        );
+        assert_eq!(
+            ethernet.event_handlers.on_mouse_left,
+            Placeholder::new("nm-connection-editor").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_block_template_params() {
+        let template = "[[block_template]]\nname=\"t\"\ntype=\"text\"\nvalue=\"${param.x}\"\n";
+        for (block, expected) in [
+            (
+                "params = { x = \"1\", y = \"2\" }",
+                "unknown parameter \"y\"",
+            ),
+            ("", "missing parameter \"x\""),
+        ] {
+            let config = format!(
+                "{}[[block]]\nname=\"a\"\ntemplate=\"t\"\n{}\n",
+                template, block
+            );
+            let dir = write_files("template-params", &[("config.toml", &config)]);
+            let error = format!("{:?}", load_from(&dir.join("config.toml")).unwrap_err());
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_block_template_error_location() {
+        let config = "[[block_template]]\nname=\"t\"\ntype=\"text\"\nvalue=\"${param.x}\"\n\
+                      [[block]]\nname=\"a\"\ntemplate=\"t\"\n\
+                      [[command]]\nname=\"c\"\ncommand=\"date\"\ninterval=\"often\"\n";
+        let dir = write_files("template-location", &[("config.toml", config)]);
+        let error = format!("{:?}", load_from(&dir.join("config.toml")).unwrap_err());
+        assert!(error.contains("line 11"), "{}", error);
+
+        let config = config.replace("interval=\"often\"", "interval=5");
+        let dir = write_files("template-location", &[("config.toml", &config)]);
+        let error = format!("{:?}", load_from(&dir.join("config.toml")).unwrap_err());
+        assert!(error.contains("missing parameter \"x\""), "{}", error);
+        assert!(error.contains("at line"), "{}", error);
+    }
+
+    #[test]
+    fn test_clock_command_name_clash() {
+        let dir = write_files(
+            "clock-command",
+            &[
+                (
+                    "config.toml",
+                    "include = [\"clock.toml\"]\n[[command]]\nname=\"time\"\ncommand=\"date\"\n",
+                ),
+                ("clock.toml", "[[clock]]\nname=\"time\"\n"),
+            ],
+        );
+        let error = format!("{:?}", load_from(&dir.join("config.toml")).unwrap_err());
+        assert!(
+            error.contains("clock \"time\" has the same name as a command"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_include_duplicate_block() {
+        let dir = write_files(
+            "include-duplicate",
+            &[
+                (
+                    "config.toml",
+                    "include = [\"other.toml\"]\n[[block]]\nname=\"a\"\ntype=\"text\"\n",
+                ),
+                ("other.toml", "[[block]]\nname=\"a\"\ntype=\"text\"\n"),
+            ],
+        );
+        let error = format!("{:?}", load_from(&dir.join("config.toml")).unwrap_err());
+        assert!(error.contains("Duplicate block \"a\""), "{}", error);
+        assert!(error.contains("other.toml"), "{}", error);
+    }
+
+    #[test]
+    fn test_themes() {
+        let dir = write_files(
+            "themes",
+            &[
+                (
+                    "config.toml",
+                    "include = [\"themes.toml\"]\nactive_theme = \"dark\"\n",
+                ),
+                (
+                    "themes.toml",
+                    "[theme.dark]\nforeground = \"#dddddd\"\n[theme.light]\nforeground = \"#222222\"\n",
+                ),
+            ],
+        );
+        let config = load_from(&dir.join("config.toml")).unwrap();
+        assert_eq!(config.active_theme.as_deref(), Some("dark"));
+        assert_eq!(
+            config.themes["light"].get("foreground").map(String::as_str),
+            Some("#222222")
+        );
+
+        let dir = write_files(
+            "themes-unknown",
+            &[("config.toml", "active_theme = \"dark\"\n")],
+        );
+        let error = format!("{:?}", load_from(&dir.join("config.toml")).unwrap_err());
+        assert!(
+            error.contains("active_theme \"dark\" is not defined"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_default_block_inherit_chain() {
+        let config: Config<Option<Placeholder>> = toml::from_str(
+            r##"
+[[default_block]]
+padding = 4
+
+[[default_block]]
+name = "base"
+foreground = "#ffffff"
+hover_underline_color = "#333333"
+active_foreground = "#00ff00"
+
+[[default_block]]
+name = "pill"
+inherit = "base"
+background = "#222222"
+pango_markup = false
+
+[[default_block]]
+name = "pill-warning"
+inherit = "pill"
+background = "#ff0000"
+
+[[block]]
+name = "disk"
+type = "enum"
+inherit = "pill-warning"
+active = "0"
+variants = "a,b"
+"##,
+        )
+        .unwrap();
+        let config = config.with_defaults().unwrap();
+        let Some(Block::Enum(block)) = config.blocks.get("disk") else {
+            panic!("{:?}", config.blocks);
+        };
+        assert_eq!(block.display.padding, Some(4.0));
+        assert_eq!(
+            block.display.decorations.foreground,
+            Placeholder::infallable("#ffffff")
+        );
+        assert_eq!(
+            block.display.decorations.background,
+            Placeholder::infallable("#ff0000")
+        );
+        assert_eq!(
+            block.display.hover_decorations.underline_color,
+            Placeholder::infallable("#333333")
+        );
+        assert_eq!(
+            block.active_display.decorations.foreground,
+            Placeholder::infallable("#00ff00")
+        );
+        assert_eq!(block.display.pango_markup, Some(false));
+    }
+
+    #[test]
+    fn test_default_block_inherit_errors() {
+        let error = |toml: &str| {
+            let config: Config<Option<Placeholder>> = toml::from_str(toml).unwrap();
+            format!("{}", config.with_defaults().unwrap_err())
+        };
+        assert_eq!(
+            error(
+                r#"
+[[default_block]]
+name = "a"
+inherit = "b"
+
+[[default_block]]
+name = "b"
+inherit = "a"
+"#
+            )
+            .replace("b -> a -> b", "a -> b -> a"),
+            "default_block inheritance cycle: a -> b -> a"
+        );
+        assert_eq!(
+            error("[[default_block]]\nname = \"a\"\ninherit = \"nope\"\n"),
+            "default_block \"a\" inherits unknown default_block \"nope\""
+        );
+    }
+
+    #[test]
+    fn test_include_missing_file() {
+        let dir = write_files(
+            "include-missing",
+            &[("config.toml", "include = [\"nope.toml\"]\n")],
+        );
+        assert!(load_from(&dir.join("config.toml")).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "schema")]
+    fn test_json_schema() {
+        let schema = serde_json::to_value(json_schema()).unwrap();
+        let enum_block = schema
+            .pointer("/definitions")
+            .and_then(|d| d.as_object())
+            .and_then(|d| d.iter().find(|(name, _)| name.starts_with("EnumBlock")))
+            .map(|(_, block)| block.clone())
+            .expect("EnumBlock definition");
+        for key in [
+            "value",
+            "foreground",
+            "hover_foreground",
+            "active_foreground",
+            "active_hover_background",
+            "on_mouse_left",
+            "show_if_matches",
+        ] {
+            assert!(
+                enum_block
+                    .pointer(&format!("/properties/{}", key))
+                    .is_some(),
+                "missing {}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_repeat_instances() {
+        let repeat = RepeatBlock::<Placeholder> {
+            name: "tray".into(),
+            prefix: "sni:sni.".into(),
+            block: "tray_item".into(),
+            phantom_data: PhantomData,
+        };
+        let vars = [
+            "sni:sni.org.kde.item-2.full_text",
+            "sni:sni.org.kde.item-2.icon_name",
+            "sni:sni.nm-applet.full_text",
+            "sni:other.full_text",
+            "clock:value",
+        ];
+        assert_eq!(
+            repeat.instances(vars.into_iter()),
+            vec!["nm-applet", "org.kde.item-2"]
+        );
+    }
+
+    #[test]
+    fn test_var_function_parse() {
+        assert_eq!(
+            VarFunction::parse("rate(disk:sectors)").unwrap(),
+            VarFunction::Rate("disk:sectors".into())
+        );
+        assert_eq!(
+            VarFunction::parse(" avg( cpu:percent , 30s )").unwrap(),
+            VarFunction::Avg("cpu:percent".into(), std::time::Duration::from_secs(30))
+        );
+        let VarFunction::Sum(pattern) = VarFunction::parse("sum(net:*.rx)").unwrap() else {
+            panic!("not a sum");
+        };
+        assert!(pattern.is_match("net:eth0.rx"));
+        assert!(!pattern.is_match("net:eth0.tx"));
+        assert!(VarFunction::parse("rate").is_err());
+        assert!(VarFunction::parse("rate(a, b)").is_err());
+        assert!(VarFunction::parse("max(a, soon)").is_err());
+        assert!(VarFunction::parse("median(a)").is_err());
+    }
+
     #[test]
     fn test_number_parse() {
         assert_eq!(Some(10.0), NumberType::Number.parse_str("  10   ").unwrap());
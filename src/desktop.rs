@@ -435,12 +435,10 @@ mod wayland_impl {
                         }
                     }
                 }
-                zwlr_foreign_toplevel_handle_v1::Event::Done => {
-                    if state.needs_print {
-                        state.needs_print = false;
-                        if let Err(e) = state.print_state() {
-                            tracing::error!("Failed to print update: {}", e);
-                        }
+                zwlr_foreign_toplevel_handle_v1::Event::Done if state.needs_print => {
+                    state.needs_print = false;
+                    if let Err(e) = state.print_state() {
+                        tracing::error!("Failed to print update: {}", e);
                     }
                 }
                 zwlr_foreign_toplevel_handle_v1::Event::Closed => {
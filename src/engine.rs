@@ -1,6 +1,6 @@
 use crossbeam_channel::Sender;
 
-use crate::{config, notify, parse, state};
+use crate::{config, notify, parse, source, state};
 
 /// Enum representing the detected display server.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -45,6 +45,8 @@ pub fn load(
     config: config::Config<parse::Placeholder>,
     state: state::State,
     notifier: notify::Notifier,
+    clicks: source::Clicks,
+    stopper: source::Stopper,
 ) -> anyhow::Result<Box<dyn Engine>> {
     let detected = detect();
 
@@ -53,7 +55,7 @@ pub fn load(
     if detected == Some(DisplayServer::Wayland) {
         tracing::info!("WAYLAND_DISPLAY is set, using Wayland engine");
         return Ok(Box::new(crate::wayland::WaylandEngine::new(
-            config, state, notifier,
+            config, state, notifier, clicks, stopper,
         )?));
     }
 
@@ -62,7 +64,7 @@ pub fn load(
     if detected == Some(DisplayServer::X11) {
         tracing::info!("DISPLAY is set, using X11 engine");
         return Ok(Box::new(crate::x11::XOrgEngine::new(
-            config, state, notifier,
+            config, state, notifier, clicks, stopper,
         )?));
     }
 
@@ -72,7 +74,7 @@ pub fn load(
     {
         tracing::info!("No display env var set, trying Wayland engine as fallback");
         return Ok(Box::new(crate::wayland::WaylandEngine::new(
-            config, state, notifier,
+            config, state, notifier, clicks, stopper,
         )?));
     }
 
@@ -82,7 +84,7 @@ pub fn load(
     {
         tracing::info!("No display env var set, trying X11 engine as fallback");
         return Ok(Box::new(crate::x11::XOrgEngine::new(
-            config, state, notifier,
+            config, state, notifier, clicks, stopper,
         )?));
     }
 
@@ -15,6 +15,7 @@ pub enum Command {
     GetProcessInfo {},
     Terminate {},
     DumpSvg { path: String, index: usize },
+    Reload {},
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
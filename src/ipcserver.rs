@@ -1,15 +1,17 @@
 use std::collections::BTreeMap;
 use std::io::prelude::*;
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::Context;
 
-use crate::{ipc, source, state, thread};
+use crate::{ipc, persist, reload, source, state, thread};
 
 #[derive(Clone)]
 pub struct Server {
     poker: source::Poker,
+    reloader: Arc<Mutex<reload::Reloader>>,
+    persister: persist::Persister,
     state_update_tx: crossbeam_channel::Sender<state::Update>,
     vars: Arc<RwLock<BTreeMap<String, String>>>,
 }
@@ -66,6 +68,17 @@ impl Server {
         Ok(Default::default())
     }
 
+    fn handle_reload(&self) -> anyhow::Result<ipc::Response> {
+        let mut reloader = self.reloader.lock().unwrap();
+        Ok(match reloader.reload() {
+            Ok(()) => Default::default(),
+            Err(e) => ipc::Response {
+                error: Some(format!("{:?}", e)),
+                ..Default::default()
+            },
+        })
+    }
+
     fn handle_get_process_info(&self) -> anyhow::Result<ipc::Response> {
         Ok(ipc::Response {
             data: Some(ipc::ResponseData::ProcessInfo {
@@ -77,9 +90,13 @@ impl Server {
     }
 
     fn handle_terminate(&self) -> anyhow::Result<ipc::Response> {
-        std::thread::spawn(|| {
+        let persister = self.persister.clone();
+        std::thread::spawn(move || {
             std::thread::sleep(std::time::Duration::from_millis(50));
             tracing::info!("Terminating via IPC request.");
+            if let Err(e) = persister.save() {
+                tracing::error!("Failed to save vars: {:?}", e);
+            }
             std::process::exit(0);
         });
         Ok(Default::default())
@@ -101,6 +118,7 @@ impl Server {
                 ipc::Command::GetProcessInfo {} => self.handle_get_process_info(),
                 ipc::Command::Terminate {} => self.handle_terminate(),
                 ipc::Command::DumpSvg { path, index } => self.handle_dump_svg(path, index),
+                ipc::Command::Reload {} => self.handle_reload(),
             }?;
             serde_json::to_writer(stream, &response)?;
         }
@@ -110,6 +128,8 @@ impl Server {
     pub fn spawn(
         instance_name: &str,
         poker: source::Poker,
+        reloader: Arc<Mutex<reload::Reloader>>,
+        persister: persist::Persister,
         state_update_tx: crossbeam_channel::Sender<state::Update>,
         var_snapshot_updates_rx: crossbeam_channel::Receiver<state::VarSnapshotUpdate>,
     ) -> anyhow::Result<()> {
@@ -128,6 +148,8 @@ impl Server {
         let socket = UnixListener::bind(&path).context("Unable to bind")?;
         let server = Server {
             poker,
+            reloader,
+            persister,
             state_update_tx,
             vars: Default::default(),
         };
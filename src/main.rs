@@ -16,6 +16,8 @@
 extern crate macro_rules_attribute;
 
 mod bar;
+mod check;
+mod clock;
 // #[allow(unused)]
 mod config;
 mod drawing;
@@ -27,12 +29,16 @@ mod logging;
 mod notify;
 #[allow(unused_macros)]
 mod parse;
+mod persist;
 mod popup_visibility;
 mod process;
 mod protocol;
+mod reload;
 mod source;
 mod state;
 mod svg_dump;
+#[cfg(test)]
+mod test_util;
 mod thread;
 mod timer;
 #[cfg(feature = "wayland")]
@@ -47,6 +53,8 @@ mod xrandr;
 mod xutils;
 
 use clap::Parser;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 #[derive(Parser)]
 #[command(
@@ -55,14 +63,74 @@ use clap::Parser;
     long_about = None)]
 #[command(propagate_version = true)]
 struct Cli {
-    /// Unique name of the oatbar server instance.
-    #[arg(long, default_value = "oatbar")]
-    instance_name: String,
+    /// Unique name of the oatbar server instance [default: oatbar]
+    #[arg(long)]
+    instance_name: Option<String>,
+    /// Path to the config file.
+    ///
+    /// Defaults to `oatbar/<instance-name>.toml` in the config dir if `--instance-name`
+    /// is given and the file exists, `oatbar/config.toml` otherwise.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Check the config for problems and exit, optionally at a different path.
+    #[arg(long, value_name = "PATH")]
+    check_config: Option<Option<PathBuf>>,
+    /// Print the JSON Schema of the config file and exit.
+    #[cfg(feature = "schema")]
+    #[arg(long)]
+    print_config_schema: bool,
+}
+
+impl Cli {
+    fn instance_name(&self) -> &str {
+        self.instance_name.as_deref().unwrap_or("oatbar")
+    }
+}
+
+fn config_path(cli: &Cli) -> anyhow::Result<PathBuf> {
+    match &cli.config {
+        Some(path) => Ok(path.clone()),
+        None => config::default_path(cli.instance_name.as_deref()),
+    }
+}
+
+fn check_config(path: PathBuf) -> anyhow::Result<()> {
+    let problems = check::check_config(&path);
+    if problems.is_empty() {
+        println!("{}: OK", path.display());
+        return Ok(());
+    }
+    for problem in problems.iter() {
+        eprintln!("{}", problem);
+    }
+    eprintln!("{} problem(s) found", problems.len());
+    std::process::exit(1);
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    #[cfg(feature = "schema")]
+    if cli.print_config_schema {
+        println!("{}", serde_json::to_string_pretty(&config::json_schema())?);
+        return Ok(());
+    }
+
+    if let Some(path) = &cli.check_config {
+        // Unlike starting the bar, checking does not write the default config.
+        let path = match (path, &cli.config) {
+            (Some(path), _) | (None, Some(path)) => path.clone(),
+            (None, None) => match config::find_path(cli.instance_name.as_deref())? {
+                Some(path) => path,
+                None => {
+                    eprintln!("No config found");
+                    std::process::exit(1);
+                }
+            },
+        };
+        return check_config(path);
+    }
+
     #[cfg(feature = "gtk4_icons")]
     gtk4::init()?;
 
@@ -73,7 +141,7 @@ fn main() -> anyhow::Result<()> {
         .build()
         .unwrap();
 
-    let _logging_guard = logging::init(&cli.instance_name)?;
+    let _logging_guard = logging::init(cli.instance_name())?;
 
     std::panic::set_hook(Box::new(|info| {
         let location = info
@@ -90,26 +158,55 @@ fn main() -> anyhow::Result<()> {
         tracing::error!("Panic at {}: {}", location, message);
     }));
 
-    let config = config::load()?;
+    let config_path = config_path(&cli)?;
+    let config = config::load_from(&config_path)?;
     let commands = config.commands.clone();
+    let clocks = config.clocks.clone();
+    let config_sources = config.sources.clone();
 
     let (ipc_server_tx, ipc_server_rx) = crossbeam_channel::unbounded();
+    let (persist_tx, persist_rx) = crossbeam_channel::unbounded();
 
-    let mut state: state::State = state::State::new(config.clone(), vec![ipc_server_tx]);
+    let persister = persist::Persister::new(cli.instance_name(), &config)?;
+    let mut state: state::State =
+        state::State::new(config.clone(), vec![ipc_server_tx, persist_tx]);
+    state.handle_var_update(persister.restore());
     state.initialize_vars();
+    persister.spawn(persist_rx)?;
+    persister.spawn_signal_handler()?;
+
+    let clicks = source::Clicks::new();
+    let stopper = source::Stopper::new();
+    let mut engine = engine::load(
+        config,
+        state,
+        notify::Notifier::new(),
+        clicks.clone(),
+        stopper.clone(),
+    )?;
 
-    let mut engine = engine::load(config, state, notify::Notifier::new())?;
-
-    let mut poker = source::Poker::new();
-    for (index, config) in commands.into_iter().enumerate() {
-        let command = source::Command { index, config };
-        let command_name = command.name();
-        command.spawn(engine.update_tx().clone(), poker.add(command_name))?;
-    }
+    let poker = source::Poker::new();
+    let mut command_set =
+        source::CommandSet::new(engine.update_tx(), poker.clone(), clicks, stopper);
+    command_set.apply(commands)?;
+    let mut clock_set = clock::ClockSet::new(engine.update_tx());
+    clock_set.apply(clocks)?;
+
+    let reloader = Arc::new(Mutex::new(reload::Reloader::new(
+        config_path,
+        config_sources,
+        command_set,
+        clock_set,
+        persister.clone(),
+        engine.update_tx(),
+    )));
+    reload::watch(reloader.clone())?;
 
     ipcserver::Server::spawn(
-        &cli.instance_name,
+        cli.instance_name(),
         poker,
+        reloader,
+        persister.clone(),
         engine.update_tx().clone(),
         ipc_server_rx,
     )?;
@@ -128,5 +225,8 @@ fn main() -> anyhow::Result<()> {
         Ok(_) => tracing::info!("Application exited normally."),
         Err(e) => tracing::error!("Application exited with error: {:?}", e),
     }
+    if let Err(e) = persister.save() {
+        tracing::error!("Failed to save vars: {:?}", e);
+    }
     result
 }
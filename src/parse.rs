@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
@@ -6,30 +8,111 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 pub trait PlaceholderContext {
     fn get(&self, key: &str) -> Option<&String>;
+    /// Names of all vars starting with `prefix`.
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str>;
+    /// Whether the var could have changed since the previous update.
+    fn changed(&self, _key: &str) -> bool {
+        true
+    }
 }
 
 impl PlaceholderContext for HashMap<String, String> {
     fn get(&self, key: &str) -> Option<&String> {
         self.get(key)
     }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        self.keys()
+            .filter(|key| key.starts_with(prefix))
+            .map(|key| key.as_str())
+            .collect()
+    }
+}
+
+/// Vars together with the names of the vars changed since the previous update.
+pub struct PlaceholderContextWithChanges<'a> {
+    pub vars: &'a HashMap<String, String>,
+    pub changed: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> PlaceholderContext for PlaceholderContextWithChanges<'a> {
+    fn get(&self, key: &str) -> Option<&String> {
+        self.vars.get(key)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        self.vars.keys_with_prefix(prefix)
+    }
+
+    fn changed(&self, key: &str) -> bool {
+        (self.changed)(key)
+    }
+}
+
+/// Vars the value of a placeholder depends on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dependencies {
+    Vars(Vec<String>),
+    /// Names looked up with `@{...}` can refer to any var.
+    Any,
+}
+
+impl Default for Dependencies {
+    fn default() -> Self {
+        Self::Vars(vec![])
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Deserialize)]
 #[serde(try_from = "String")]
 pub struct Placeholder {
     tokens: Arc<Vec<Token>>,
+    dependencies: Arc<Dependencies>,
+    /// Set once the value is resolved, later updates are skipped unless dependencies change.
+    resolved: bool,
     pub value: String,
 }
 
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Placeholder {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Placeholder".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "type": "string",
+            "description": "Text that can reference variables as ${var}.",
+        })
+    }
+}
+
 impl Placeholder {
     pub fn new(expr: &str) -> anyhow::Result<Self> {
         let tokens =
             parse_expr(expr).with_context(|| format!("Failed to parse expression: {:?}", expr))?;
+        let mut indirect = false;
+        let mut names = vec![];
+        visit_var_tokens(&tokens, &mut |var| match var.indirect_name {
+            Some(_) => indirect = true,
+            None => names.push(var.name.clone()),
+        });
+        let dependencies = if indirect {
+            Dependencies::Any
+        } else {
+            names.sort();
+            names.dedup();
+            Dependencies::Vars(names)
+        };
         Ok(Self {
             tokens: Arc::new(tokens),
+            dependencies: Arc::new(dependencies),
+            resolved: false,
             value: "".into(),
         })
     }
@@ -39,7 +122,13 @@ impl Placeholder {
     }
 
     pub fn update(&mut self, vars: &dyn PlaceholderContext) -> anyhow::Result<bool> {
+        if !self.needs_update(vars) {
+            return Ok(false);
+        }
+        // Keep failing placeholders unresolved, so that the error is reported again.
+        self.resolved = false;
         let new_value = self.resolve(vars)?;
+        self.resolved = true;
         let updated = new_value != self.value;
         if updated {
             self.value = new_value;
@@ -47,9 +136,35 @@ impl Placeholder {
         Ok(updated)
     }
 
+    #[cfg(test)]
+    pub fn dependencies(&self) -> &Dependencies {
+        &self.dependencies
+    }
+
+    /// Whether `update` has to resolve the value, because it is new or its dependencies changed.
+    pub fn needs_update(&self, vars: &dyn PlaceholderContext) -> bool {
+        !self.resolved
+            || match self.dependencies.as_ref() {
+                Dependencies::Vars(names) => names.iter().any(|name| vars.changed(name)),
+                Dependencies::Any => true,
+            }
+    }
+
+    /// Makes the next `update` resolve the value regardless of changes.
+    pub fn invalidate(&mut self) {
+        self.resolved = false;
+    }
+
     pub fn is_empty(&self) -> bool {
         self.tokens.is_empty()
     }
+
+    /// Names of the vars referenced by this placeholder.
+    pub fn var_names(&self) -> Vec<&str> {
+        let mut names = vec![];
+        collect_var_names(&self.tokens, &mut names);
+        names
+    }
 }
 
 impl Deref for Placeholder {
@@ -76,20 +191,86 @@ pub trait PlaceholderExt {
 impl PlaceholderExt for Placeholder {
     type R = String;
     fn resolve(&self, vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
-        Ok(self
-            .tokens
-            .iter()
-            .map(|token| match token {
-                Token::String(s) => Ok(s.clone()),
-                Token::Var(v) => v
-                    .resolve(vars)
-                    .with_context(|| format!("Cannot resolve variable: {:?}", v.name)),
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?
-            .join(""))
+        resolve_tokens(&self.tokens, vars)
     }
 }
 
+fn resolve_tokens(tokens: &[Token], vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
+    Ok(tokens
+        .iter()
+        .map(|token| match token {
+            Token::String(s) => Ok(s.clone()),
+            Token::Var(v) => v
+                .resolve(vars)
+                .with_context(|| format!("Cannot resolve variable: {:?}", v.name)),
+            Token::If(c) => c.resolve(vars).context("Cannot resolve condition"),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .join(""))
+}
+
+fn collect_var_names<'a>(tokens: &'a [Token], names: &mut Vec<&'a str>) {
+    visit_var_tokens(tokens, &mut |var| {
+        if var.indirect_name.is_none() {
+            names.push(&var.name)
+        }
+    });
+}
+
+/// Calls `visit` for every var, including the ones nested in names, filters and conditions.
+fn visit_var_tokens<'a>(tokens: &'a [Token], visit: &mut dyn FnMut(&'a VarToken)) {
+    for token in tokens.iter() {
+        match token {
+            Token::String(_) => {}
+            Token::Var(v) => {
+                visit(v);
+                if let Some(tokens) = &v.indirect_name {
+                    visit_var_tokens(tokens, visit);
+                }
+                for filter in v.filters.iter() {
+                    match filter {
+                        Filter::Conditional(c) => {
+                            visit_var_tokens(&c.operand, visit);
+                            visit_var_tokens(&c.then, visit);
+                            visit_var_tokens(&c.otherwise, visit);
+                        }
+                        Filter::Dynamic(d) => visit_var_tokens(&d.args, visit),
+                        _ => {}
+                    }
+                }
+            }
+            Token::If(c) => {
+                visit_var_tokens(&c.left, visit);
+                if let Some((_, right)) = &c.comparison {
+                    visit_var_tokens(right, visit);
+                }
+                visit_var_tokens(&c.then, visit);
+                visit_var_tokens(&c.otherwise, visit);
+            }
+        }
+    }
+}
+
+/// Number of cells an extended grapheme cluster occupies.
+fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width()
+}
+
+fn display_width(input: &str) -> usize {
+    input.graphemes(true).map(grapheme_width).sum()
+}
+
+/// Takes graphemes while they fit into `width` cells.
+fn fit_width<'a>(graphemes: impl Iterator<Item = &'a str>, width: usize) -> Vec<&'a str> {
+    let mut used = 0;
+    graphemes
+        .take_while(|grapheme| {
+            used += grapheme_width(grapheme);
+            used <= width
+        })
+        .collect()
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AlignDirection {
     Left,
@@ -133,56 +314,421 @@ impl Align {
     }
 
     fn apply(&self, input: &str) -> anyhow::Result<String> {
-        let len = input.chars().count();
-        let pad_left = match self.direction {
-            AlignDirection::Right => self.width.checked_sub(len).unwrap_or_default(),
-            AlignDirection::Left => 0,
-            AlignDirection::Center => self.width.checked_sub(len).unwrap() / 2,
-        };
-        let pad_right = match self.direction {
-            AlignDirection::Right => 0,
-            AlignDirection::Left => self.width.checked_sub(len).unwrap_or_default(),
-            AlignDirection::Center => self.width.checked_sub(len).unwrap() / 2,
-        };
-        let pad_right_extra = match self.direction {
-            AlignDirection::Center => self.width.checked_sub(len).unwrap() % 2,
-            _ => 0,
+        let padding = self.width.saturating_sub(display_width(input));
+        let (pad_left, pad_right) = match self.direction {
+            AlignDirection::Left => (0, padding),
+            AlignDirection::Right => (padding, 0),
+            AlignDirection::Center => (padding / 2, padding - padding / 2),
         };
         let space = self.space.unwrap_or(' ');
-        let mut result = String::with_capacity(self.width * 2);
+        let mut result = String::with_capacity(input.len() + padding * space.len_utf8());
         for _ in 0..pad_left {
             result.push(space);
         }
         result.push_str(input);
-        for _ in 0..(pad_right + pad_right_extra) {
+        for _ in 0..pad_right {
             result.push(space);
         }
         Ok(result)
     }
 }
 
+/// `max:width[:mid][:ellipsis]`, truncating the end or the middle of the input.
+#[derive(Debug, Clone, PartialEq)]
+struct MaxFilter {
+    width: usize,
+    ellipsis: String,
+    middle: bool,
+}
+
+impl MaxFilter {
+    fn parse(expression: &str) -> anyhow::Result<Self> {
+        let (width, options) = match expression.split_once(':') {
+            Some((width, options)) => (width, Some(options)),
+            None => (expression, None),
+        };
+        let (middle, ellipsis) = match options {
+            None => (false, "..."),
+            Some("mid") => (true, "..."),
+            Some(options) => match options.strip_prefix("mid:") {
+                Some(ellipsis) => (true, ellipsis),
+                None => (false, options),
+            },
+        };
+        Ok(Self {
+            width: width.trim().parse()?,
+            ellipsis: ellipsis.to_string(),
+            middle,
+        })
+    }
+
+    fn apply(&self, input: &str) -> String {
+        if display_width(input) <= self.width {
+            return input.to_string();
+        }
+        let ellipsis_width = display_width(&self.ellipsis);
+        if ellipsis_width > self.width {
+            return fit_width(input.graphemes(true), self.width).concat();
+        }
+        let available = self.width - ellipsis_width;
+        if !self.middle {
+            return fit_width(input.graphemes(true), available).concat() + &self.ellipsis;
+        }
+        let head = fit_width(input.graphemes(true), available.div_ceil(2));
+        let head_width: usize = head.iter().map(|g| grapheme_width(g)).sum();
+        let mut tail = fit_width(input.graphemes(true).rev(), available - head_width);
+        tail.reverse();
+        head.concat() + &self.ellipsis + &tail.concat()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Comparison {
+    fn from_filter(name: &str) -> Option<Self> {
+        Some(match name {
+            "eq" => Self::Eq,
+            "ne" => Self::Ne,
+            "gt" => Self::Gt,
+            "ge" => Self::Ge,
+            "lt" => Self::Lt,
+            "le" => Self::Le,
+            _ => return None,
+        })
+    }
+
+    fn from_operator(operator: &str) -> Option<Self> {
+        Some(match operator {
+            "==" => Self::Eq,
+            "!=" => Self::Ne,
+            ">" => Self::Gt,
+            ">=" => Self::Ge,
+            "<" => Self::Lt,
+            "<=" => Self::Le,
+            _ => return None,
+        })
+    }
+
+    /// Compares as numbers if both sides are numbers, as strings otherwise.
+    fn compare(self, left: &str, right: &str) -> bool {
+        let ordering = match (left.trim().parse::<f64>(), right.trim().parse::<f64>()) {
+            (Ok(left), Ok(right)) => left.partial_cmp(&right),
+            _ => Some(left.cmp(right)),
+        };
+        match self {
+            Self::Eq => ordering == Some(Ordering::Equal),
+            Self::Ne => ordering != Some(Ordering::Equal),
+            Self::Gt => ordering == Some(Ordering::Greater),
+            Self::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            Self::Lt => ordering == Some(Ordering::Less),
+            Self::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        }
+    }
+}
+
+/// `<op>:<operand>?<then>:<otherwise>`, e.g. `gt:20?ok:low`.
+#[derive(Debug, Clone, PartialEq)]
+struct ConditionalFilter {
+    comparison: Comparison,
+    operand: Vec<Token>,
+    then: Vec<Token>,
+    otherwise: Vec<Token>,
+}
+
+impl ConditionalFilter {
+    fn parse(comparison: Comparison, args: &str) -> anyhow::Result<Self> {
+        let (operand, branches) = split_once_top_level(args, '?').ok_or_else(|| {
+            anyhow::anyhow!(
+                "Conditional filter format must be op:value?then:else, found: {:?}",
+                args
+            )
+        })?;
+        let (then, otherwise) = split_once_top_level(branches, ':').unwrap_or((branches, ""));
+        Ok(Self {
+            comparison,
+            operand: parse_expr(operand)?,
+            then: parse_expr(then)?,
+            otherwise: parse_expr(otherwise)?,
+        })
+    }
+
+    fn apply(&self, input: &str, vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
+        let operand = resolve_tokens(&self.operand, vars)?;
+        if self.comparison.compare(input, &operand) {
+            resolve_tokens(&self.then, vars)
+        } else {
+            resolve_tokens(&self.otherwise, vars)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arithmetic {
+    Mul,
+    Div,
+    Add,
+}
+
+/// `fmt:[0][width][.precision]`, e.g. `fmt:.1` or `fmt:05.1`.
+#[derive(Debug, Clone, PartialEq)]
+struct NumberFormat {
+    zero_padded: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl NumberFormat {
+    fn parse(expression: &str) -> anyhow::Result<Self> {
+        let (width, precision) = match expression.trim().split_once('.') {
+            Some((width, precision)) => (width, Some(precision.parse()?)),
+            None => (expression.trim(), None),
+        };
+        Ok(Self {
+            zero_padded: width.starts_with('0'),
+            width: if width.is_empty() { 0 } else { width.parse()? },
+            precision,
+        })
+    }
+
+    fn apply(&self, value: f64) -> String {
+        let width = self.width;
+        match (self.zero_padded, self.precision) {
+            (true, Some(precision)) => format!("{:0width$.precision$}", value),
+            (true, None) => format!("{:0width$}", value),
+            (false, Some(precision)) => format!("{:width$.precision$}", value),
+            (false, None) => format!("{:width$}", value),
+        }
+    }
+}
+
+fn parse_number(expression: &str) -> anyhow::Result<f64> {
+    expression
+        .trim()
+        .parse()
+        .with_context(|| format!("Not a number: {:?}", expression))
+}
+
+/// Applies `f` to numeric input, other input is left as is.
+fn map_number(input: &str, f: impl FnOnce(f64) -> String) -> String {
+    match input.trim().parse::<f64>() {
+        Ok(value) => f(value),
+        Err(_) => input.to_string(),
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    let seconds = seconds % 60;
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// `replace:<regex>:<replacement>`, the regex can contain `:`, the replacement can not.
+#[derive(Debug, Clone)]
+struct ReplaceFilter {
+    regex: regex::Regex,
+    replacement: String,
+}
+
+impl PartialEq for ReplaceFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str() && self.replacement == other.replacement
+    }
+}
+
+impl ReplaceFilter {
+    fn parse(expression: &str) -> anyhow::Result<Self> {
+        let (regex, replacement) = expression.rsplit_once(':').ok_or_else(|| {
+            anyhow::anyhow!(
+                "Replace filter format must be replace:regex:replacement, found: {:?}",
+                expression
+            )
+        })?;
+        Ok(Self {
+            regex: regex::Regex::new(regex)?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+/// `split:<separator>:<index>`, negative indexes count from the end.
+#[derive(Debug, Clone, PartialEq)]
+struct SplitFilter {
+    separator: String,
+    index: i64,
+}
+
+impl SplitFilter {
+    fn parse(expression: &str) -> anyhow::Result<Self> {
+        let (separator, index) = expression
+            .rsplit_once(':')
+            .filter(|(separator, _)| !separator.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Split filter format must be split:separator:index, found: {:?}",
+                    expression
+                )
+            })?;
+        Ok(Self {
+            separator: separator.to_string(),
+            index: index.trim().parse()?,
+        })
+    }
+
+    fn apply(&self, input: &str) -> String {
+        let parts: Vec<&str> = input.split(self.separator.as_str()).collect();
+        let index = if self.index < 0 {
+            parts.len() as i64 + self.index
+        } else {
+            self.index
+        };
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| parts.get(index))
+            .unwrap_or(&"")
+            .to_string()
+    }
+}
+
+fn title_case(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut word_start = true;
+    for c in input.chars() {
+        if word_start {
+            result.extend(c.to_uppercase());
+        } else {
+            result.extend(c.to_lowercase());
+        }
+        word_start = !c.is_alphanumeric();
+    }
+    result
+}
+
+fn pango_escape(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '\'' => result.push_str("&#39;"),
+            '"' => result.push_str("&quot;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Filters with arguments that can contain placeholders.
+const ARG_FILTERS: &[&str] = &[
+    "def", "align", "max", "mul", "div", "add", "round", "fmt", "replace", "split",
+];
+
+/// A filter with placeholders in its arguments, parsed once they are resolved.
+#[derive(Debug, Clone, PartialEq)]
+struct DynamicFilter {
+    name: String,
+    args: Vec<Token>,
+}
+
+impl DynamicFilter {
+    fn apply(&self, input: &str, vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
+        let args = resolve_tokens(&self.args, vars)?;
+        Filter::parse_static(&self.name, &args)?.apply(input, vars)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 enum Filter {
     DefaultValue(String),
-    Max(usize),
+    Max(MaxFilter),
     Align(Align),
+    Conditional(ConditionalFilter),
+    Arithmetic(Arithmetic, f64),
+    Round(i32),
+    Format(NumberFormat),
+    Bytes,
+    BytesSi,
+    Duration,
+    Upper,
+    Lower,
+    Title,
+    Trim,
+    Replace(ReplaceFilter),
+    Split(SplitFilter),
+    PangoEscape,
+    Dynamic(DynamicFilter),
 }
 
 impl Filter {
     fn parse(expression: &str) -> anyhow::Result<Self> {
-        match expression.trim_start().split_once(':') {
-            Some(("def", v)) => Ok(Filter::DefaultValue(v.to_string())),
-            Some(("align", v)) => Ok(Filter::Align(Align::parse(v)?)),
-            Some(("max", v)) => Ok(Filter::Max(v.parse()?)),
-            Some((name, _)) => Err(anyhow::anyhow!("Unknown filter: {:?}", name)),
-            None => Err(anyhow::anyhow!(
-                "Filter format must be filter:args..., found: {:?}",
-                expression
-            )),
+        let expression = expression.trim_start();
+        match expression.split_once(':') {
+            // Branches of conditional filters are placeholders already.
+            Some((name, v))
+                if (v.contains("${") || v.contains("@{")) && ARG_FILTERS.contains(&name) =>
+            {
+                Ok(Filter::Dynamic(DynamicFilter {
+                    name: name.to_string(),
+                    args: parse_expr(v)?,
+                }))
+            }
+            Some((name, v)) => Self::parse_static(name, v),
+            None => match expression.trim_end() {
+                "bytes" => Ok(Filter::Bytes),
+                "bytes_si" => Ok(Filter::BytesSi),
+                "duration" => Ok(Filter::Duration),
+                "upper" => Ok(Filter::Upper),
+                "lower" => Ok(Filter::Lower),
+                "title" => Ok(Filter::Title),
+                "trim" => Ok(Filter::Trim),
+                "pango_escape" => Ok(Filter::PangoEscape),
+                _ => Err(anyhow::anyhow!(
+                    "Filter format must be filter:args..., found: {:?}",
+                    expression
+                )),
+            },
         }
     }
 
-    fn apply(&self, input: &str) -> anyhow::Result<String> {
+    fn parse_static(name: &str, v: &str) -> anyhow::Result<Self> {
+        match name {
+            "def" => Ok(Filter::DefaultValue(v.to_string())),
+            "align" => Ok(Filter::Align(Align::parse(v)?)),
+            "max" => Ok(Filter::Max(MaxFilter::parse(v)?)),
+            "mul" => Ok(Filter::Arithmetic(Arithmetic::Mul, parse_number(v)?)),
+            "div" => match parse_number(v)? {
+                0.0 => Err(anyhow::anyhow!("Division by zero")),
+                v => Ok(Filter::Arithmetic(Arithmetic::Div, v)),
+            },
+            "add" => Ok(Filter::Arithmetic(Arithmetic::Add, parse_number(v)?)),
+            "round" => Ok(Filter::Round(v.trim().parse()?)),
+            "fmt" => Ok(Filter::Format(NumberFormat::parse(v)?)),
+            "replace" => Ok(Filter::Replace(ReplaceFilter::parse(v)?)),
+            "split" => Ok(Filter::Split(SplitFilter::parse(v)?)),
+            name => match Comparison::from_filter(name) {
+                Some(comparison) => Ok(Filter::Conditional(ConditionalFilter::parse(
+                    comparison, v,
+                )?)),
+                None => Err(anyhow::anyhow!("Unknown filter: {:?}", name)),
+            },
+        }
+    }
+
+    fn apply(&self, input: &str, vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
         Ok(match self {
             Self::DefaultValue(v) => {
                 if input.trim().is_empty() {
@@ -191,21 +737,46 @@ impl Filter {
                     input.to_string()
                 }
             }
-            Self::Max(max_length) => {
-                if input.chars().count() > *max_length {
-                    let mut result = String::with_capacity(max_length * 2);
-                    let ellipsis = "...";
-                    let truncate_len = std::cmp::max(max_length - ellipsis.len(), 0);
-                    for ch in input.chars().take(truncate_len) {
-                        result.push(ch);
-                    }
-                    result.push_str(ellipsis);
-                    result
-                } else {
-                    input.to_string()
-                }
-            }
+            Self::Max(max) => max.apply(input),
             Self::Align(align) => align.apply(input)?,
+            Self::Conditional(conditional) => conditional.apply(input, vars)?,
+            Self::Arithmetic(op, operand) => map_number(input, |value| {
+                match op {
+                    Arithmetic::Mul => value * operand,
+                    Arithmetic::Div => value / operand,
+                    Arithmetic::Add => value + operand,
+                }
+                .to_string()
+            }),
+            Self::Round(digits) => map_number(input, |value| {
+                let factor = 10f64.powi(*digits);
+                ((value * factor).round() / factor).to_string()
+            }),
+            Self::Format(format) => map_number(input, |value| format.apply(value)),
+            Self::Bytes => map_number(input, |value| {
+                bytesize::ByteSize::b(value.max(0.0) as u64)
+                    .display()
+                    .iec()
+                    .to_string()
+            }),
+            Self::BytesSi => map_number(input, |value| {
+                bytesize::ByteSize::b(value.max(0.0) as u64)
+                    .display()
+                    .si()
+                    .to_string()
+            }),
+            Self::Duration => map_number(input, format_duration),
+            Self::Upper => input.to_uppercase(),
+            Self::Lower => input.to_lowercase(),
+            Self::Title => title_case(input),
+            Self::Trim => input.trim().to_string(),
+            Self::Replace(replace) => replace
+                .regex
+                .replace_all(input, replace.replacement.as_str())
+                .into_owned(),
+            Self::Split(split) => split.apply(input),
+            Self::PangoEscape => pango_escape(input),
+            Self::Dynamic(dynamic) => dynamic.apply(input, vars)?,
         })
     }
 }
@@ -213,77 +784,259 @@ impl Filter {
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct VarToken {
     pub name: String,
+    /// Set if the name looks up other vars with `@{...}`.
+    indirect_name: Option<Vec<Token>>,
     filters: Vec<Filter>,
 }
 
+/// Resolves `${xdg:<dir>}` to the corresponding XDG base directory.
+fn xdg_dir(name: &str) -> anyhow::Result<Option<std::path::PathBuf>> {
+    Ok(match name {
+        "config" => dirs::config_dir(),
+        "data" => dirs::data_dir(),
+        "cache" => dirs::cache_dir(),
+        "state" => dirs::state_dir(),
+        "runtime" => dirs::runtime_dir(),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unknown XDG directory: {:?}, expected one of config, data, cache, state, runtime",
+                name
+            ))
+        }
+    })
+}
+
 impl VarToken {
     fn parse(expression: &str) -> anyhow::Result<Self> {
-        let mut split = expression.split('|');
+        let mut split = split_top_level(expression, '|').into_iter();
         let var = split.next().unwrap().trim();
+        let indirect_name = if var.contains("@{") {
+            Some(parse_indirect_name(var)?)
+        } else {
+            if let Some(dir) = var.strip_prefix("xdg:") {
+                xdg_dir(dir)?;
+            }
+            None
+        };
         let filters = split
             .map(Filter::parse)
             .collect::<anyhow::Result<Vec<_>>>()?;
         Ok(VarToken {
             name: var.to_string(),
+            indirect_name,
             filters,
         })
     }
 
     pub fn resolve(&self, vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
-        let mut value = vars.get(&self.name).cloned().unwrap_or_default();
+        let name = match &self.indirect_name {
+            Some(tokens) => Cow::Owned(resolve_tokens(tokens, vars)?),
+            None => Cow::Borrowed(&self.name),
+        };
+        let mut value = match name.split_once(':') {
+            Some(("env", name)) => std::env::var(name).unwrap_or_default(),
+            Some(("xdg", dir)) => xdg_dir(dir)?
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            _ => vars.get(&name).cloned().unwrap_or_default(),
+        };
         for filter in self.filters.iter() {
-            value = filter.apply(&value)?;
+            value = filter.apply(&value, vars)?;
         }
         Ok(value)
     }
 }
 
+/// Parses a var name like `stats:net.@{iface}.ipv4`, where `@{iface}` is the value
+/// of the `iface` var.
+fn parse_indirect_name(name: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = name;
+    while let Some(pos) = rest.find("@{") {
+        if pos > 0 {
+            tokens.push(Token::String(rest[..pos].to_string()));
+        }
+        let var = &rest[pos + 2..];
+        let end = find_top_level(var, |c| c == '}')
+            .ok_or_else(|| anyhow::anyhow!("Non-closed @{{ in {:?}", name))?;
+        tokens.push(Token::Var(VarToken::parse(&var[..end])?));
+        rest = &var[end + 1..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::String(rest.to_string()));
+    }
+    Ok(tokens)
+}
+
+/// `${if <condition> then <value> else <value>}`.
+///
+/// The condition is either a single value, true if it is not empty, or two values
+/// compared with `==`, `!=`, `>`, `>=`, `<` or `<=`. Values are var names, numbers or
+/// quoted strings, which can contain placeholders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conditional {
+    left: Vec<Token>,
+    comparison: Option<(Comparison, Vec<Token>)>,
+    then: Vec<Token>,
+    otherwise: Vec<Token>,
+}
+
+impl Conditional {
+    fn parse(expression: &str) -> anyhow::Result<Self> {
+        let error = || {
+            anyhow::anyhow!(
+                "Condition format must be ${{if <condition> then <value> else <value>}}, found: {:?}",
+                expression
+            )
+        };
+        let words = split_words(expression)?;
+        let then_pos = words.iter().position(|w| *w == "then").ok_or_else(error)?;
+        let (then, otherwise) = match &words[then_pos + 1..] {
+            [then] => (*then, "\"\""),
+            [then, "else", otherwise] => (*then, *otherwise),
+            _ => return Err(error()),
+        };
+        let (left, comparison) = match &words[..then_pos] {
+            [value] => (*value, None),
+            [left, operator, right] => {
+                let comparison = Comparison::from_operator(operator)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown comparison: {:?}", operator))?;
+                (*left, Some((comparison, parse_operand(right)?)))
+            }
+            _ => return Err(error()),
+        };
+        Ok(Self {
+            left: parse_operand(left)?,
+            comparison,
+            then: parse_operand(then)?,
+            otherwise: parse_operand(otherwise)?,
+        })
+    }
+
+    fn resolve(&self, vars: &dyn PlaceholderContext) -> anyhow::Result<String> {
+        let left = resolve_tokens(&self.left, vars)?;
+        let matches = match &self.comparison {
+            Some((comparison, right)) => comparison.compare(&left, &resolve_tokens(right, vars)?),
+            None => !left.trim().is_empty(),
+        };
+        if matches {
+            resolve_tokens(&self.then, vars)
+        } else {
+            resolve_tokens(&self.otherwise, vars)
+        }
+    }
+}
+
+/// A quoted string, a number, a placeholder or a var name.
+fn parse_operand(word: &str) -> anyhow::Result<Vec<Token>> {
+    if let Some(quoted) = word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+        parse_expr(quoted)
+    } else if word.parse::<f64>().is_ok() || word.starts_with("${") {
+        parse_expr(word)
+    } else {
+        Ok(vec![Token::Var(VarToken::parse(word)?)])
+    }
+}
+
+/// Splits on whitespace, keeping quoted strings and placeholders whole.
+fn split_words(expression: &str) -> anyhow::Result<Vec<&str>> {
+    let mut words = vec![];
+    let mut rest = expression.trim_start();
+    while !rest.is_empty() {
+        let len = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                quoted
+                    .find('"')
+                    .ok_or_else(|| anyhow::anyhow!("Non-closed quote in {:?}", expression))?
+                    + 2
+            }
+            None => find_top_level(rest, char::is_whitespace).unwrap_or(rest.len()),
+        };
+        words.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    Ok(words)
+}
+
+/// Position of the first char matching `predicate` outside of nested `${...}` and `@{...}`.
+fn find_top_level(expression: &str, predicate: impl Fn(char) -> bool) -> Option<usize> {
+    let mut depth = 0;
+    let mut prev = None;
+    for (pos, c) in expression.char_indices() {
+        match c {
+            '{' if matches!(prev, Some('$' | '@')) => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if depth == 0 && predicate(c) => return Some(pos),
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+fn split_once_top_level(expression: &str, separator: char) -> Option<(&str, &str)> {
+    let pos = find_top_level(expression, |c| c == separator)?;
+    Some((
+        &expression[..pos],
+        &expression[pos + separator.len_utf8()..],
+    ))
+}
+
+fn split_top_level(mut expression: &str, separator: char) -> Vec<&str> {
+    let mut result = vec![];
+    while let Some((item, rest)) = split_once_top_level(expression, separator) {
+        result.push(item);
+        expression = rest;
+    }
+    result.push(expression);
+    result
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     String(String),
     Var(VarToken),
+    If(Box<Conditional>),
+}
+
+fn parse_token(expression: &str) -> anyhow::Result<Token> {
+    match expression.trim_start().strip_prefix("if ") {
+        Some(condition) => Ok(Token::If(Box::new(Conditional::parse(condition)?))),
+        None => Ok(Token::Var(VarToken::parse(expression)?)),
+    }
 }
 
 pub fn parse_expr(expression: &str) -> anyhow::Result<Vec<Token>> {
     let mut result = Vec::<Token>::with_capacity(5);
-    let mut char_iter = expression.chars();
     let mut string_buf = String::with_capacity(255);
-    while let Some(char) = char_iter.next() {
-        match char {
-            '$' => match char_iter.next() {
-                Some('{') => {
-                    let mut var = Vec::<char>::with_capacity(255);
-                    loop {
-                        match char_iter.next() {
-                            Some('}') => {
-                                if !string_buf.is_empty() {
-                                    result.push(Token::String(string_buf.clone()));
-                                    string_buf.clear();
-                                }
-
-                                let var: String = var.into_iter().collect();
-                                let var_token = VarToken::parse(&var)?;
-                                result.push(Token::Var(var_token));
-                                break;
-                            }
-                            Some(other) => {
-                                var.push(other);
-                            }
-                            None => return Err(anyhow::anyhow!("Non-closed placeholder")),
-                        }
-                    }
-                }
-                Some(other) => {
-                    string_buf.push('$');
-                    string_buf.push(other);
+    let mut rest = expression;
+    while let Some(pos) = rest.find('$') {
+        string_buf.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        match after.chars().next() {
+            Some('{') => {
+                let var = &after[1..];
+                // Nested placeholders are skipped to find the matching brace.
+                let end = find_top_level(var, |c| c == '}')
+                    .ok_or_else(|| anyhow::anyhow!("Non-closed placeholder"))?;
+                if !string_buf.is_empty() {
+                    result.push(Token::String(string_buf.clone()));
+                    string_buf.clear();
                 }
-                None => {
-                    return Err(anyhow::anyhow!("Unescaped $ at the end of the string"));
-                }
-            },
-            char => string_buf.push(char),
+                result.push(parse_token(&var[..end])?);
+                rest = &var[end + 1..];
+            }
+            Some(other) => {
+                string_buf.push('$');
+                string_buf.push(other);
+                rest = &after[other.len_utf8()..];
+            }
+            None => {
+                return Err(anyhow::anyhow!("Unescaped $ at the end of the string"));
+            }
         }
     }
+    string_buf.push_str(rest);
     if !string_buf.is_empty() {
         result.push(Token::String(string_buf.clone()));
         string_buf.clear();
@@ -357,6 +1110,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_unicode_width() {
+        let mut map = HashMap::new();
+        map.insert("jp".into(), "日本語のタイトル".into());
+        map.insert("emoji".into(), "👩\u{200D}💻 code".into());
+        map.insert("accent".into(), "e\u{301}te\u{301}".into());
+        map.insert(
+            "path".into(),
+            "/home/user/projects/oatbar/src/parse.rs".into(),
+        );
+        map.insert("a".into(), "hello world".into());
+        let resolve = |expr: &str| Placeholder::new(expr).unwrap().resolve(&map).unwrap();
+        assert_eq!(resolve("${jp|max:10}"), "日本語...");
+        assert_eq!(resolve("${jp|max:10:…}"), "日本語の…");
+        assert_eq!(resolve("${jp|align:-<20}"), "日本語のタイトル----");
+        assert_eq!(resolve("${emoji|max:5}"), "👩\u{200D}💻...");
+        assert_eq!(resolve("${emoji|align:_>9}"), "__👩\u{200D}💻 code");
+        assert_eq!(resolve("${accent|max:3}"), "e\u{301}te\u{301}");
+        assert_eq!(resolve("${accent|align:.^5}"), ".e\u{301}te\u{301}.");
+        assert_eq!(resolve("${path|max:20:mid}"), "/home/use...parse.rs");
+        assert_eq!(resolve("${path|max:20:mid:…}"), "/home/user…/parse.rs");
+        assert_eq!(resolve("${a|max:4:}"), "hell");
+        assert_eq!(resolve("${a|max:2}"), "he");
+        assert_eq!(resolve("${a|align:-^5}"), "hello world");
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let mut map = HashMap::new();
+        map.insert("a".into(), "1".into());
+        map.insert("b".into(), "2".into());
+        let mut placeholder = Placeholder::new("${a} ${b|def:${c}} ${a}").unwrap();
+        assert_eq!(
+            placeholder.dependencies(),
+            &Dependencies::Vars(vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(
+            Placeholder::new("${@{a}.x}").unwrap().dependencies(),
+            &Dependencies::Any
+        );
+
+        let nothing_changed = |_: &str| false;
+        let a_changed = |name: &str| name == "a";
+        // Resolved on the first update regardless of changes.
+        let context = PlaceholderContextWithChanges {
+            vars: &map,
+            changed: &nothing_changed,
+        };
+        assert!(placeholder.update(&context).unwrap());
+        assert_eq!(placeholder.value, "1 2 1");
+
+        map.insert("b".into(), "3".into());
+        let context = PlaceholderContextWithChanges {
+            vars: &map,
+            changed: &nothing_changed,
+        };
+        assert!(!placeholder.update(&context).unwrap());
+        assert_eq!(placeholder.value, "1 2 1");
+
+        map.insert("a".into(), "4".into());
+        let context = PlaceholderContextWithChanges {
+            vars: &map,
+            changed: &a_changed,
+        };
+        assert!(placeholder.update(&context).unwrap());
+        assert_eq!(placeholder.value, "4 3 4");
+
+        placeholder.invalidate();
+        assert!(!placeholder.update(&map).unwrap());
+        assert!(placeholder.needs_update(&map));
+    }
+
     #[test]
     fn test_value() {
         let mut map = HashMap::new();
@@ -364,8 +1189,149 @@ mod tests {
         map.insert("bar".into(), "world".into());
         map.insert("baz".into(), "unuzed".into());
         let value = "<test> ${foo} $$ ${bar}, (${not_found}) ${not_found|def:default} </test>";
-        let result = Placeholder::new(&value).unwrap().resolve(&map);
+        let result = Placeholder::new(value).unwrap().resolve(&map);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "<test> hello $$ world, () default </test>");
     }
+
+    #[test]
+    fn test_env() {
+        std::env::set_var("OATBAR_TEST_ENV", "value");
+        let map = HashMap::new();
+        assert_eq!(
+            Placeholder::new("${env:OATBAR_TEST_ENV} ${env:OATBAR_TEST_UNSET|def:unknown}")
+                .unwrap()
+                .resolve(&map)
+                .unwrap(),
+            "value unknown"
+        );
+        assert_eq!(
+            Placeholder::new("${xdg:config}")
+                .unwrap()
+                .resolve(&map)
+                .unwrap(),
+            dirs::config_dir().unwrap().to_string_lossy()
+        );
+        assert!(Placeholder::new("${xdg:nope}").is_err());
+    }
+
+    #[test]
+    fn test_conditional_filter() {
+        let mut map = HashMap::new();
+        map.insert("charge".into(), "85".into());
+        map.insert("low".into(), "5".into());
+        map.insert("red".into(), "#ff0000".into());
+        let resolve = |expr: &str| Placeholder::new(expr).unwrap().resolve(&map).unwrap();
+        assert_eq!(resolve("${charge|gt:20?ok:low}"), "ok");
+        assert_eq!(resolve("${low|gt:20?ok:low}"), "low");
+        assert_eq!(resolve("${low|gt:20?ok}"), "");
+        assert_eq!(resolve("${low|le:${charge}?${red}:none}"), "#ff0000");
+        assert_eq!(resolve("${charge|eq:85.0?yes:no}"), "yes");
+        assert_eq!(resolve("${missing|eq:?empty:set}"), "empty");
+        assert!(Placeholder::new("${charge|gt:20}").is_err());
+    }
+
+    #[test]
+    fn test_if() {
+        let mut map = HashMap::new();
+        map.insert("status".into(), "Charging".into());
+        map.insert("charge".into(), "9".into());
+        let resolve = |expr: &str| Placeholder::new(expr).unwrap().resolve(&map).unwrap();
+        assert_eq!(
+            resolve(r#"${if status == "Charging" then "⚡" else ""}"#),
+            "⚡"
+        );
+        assert_eq!(
+            resolve(r#"${if charge < 10 then "${charge}%!" else charge}"#),
+            "9%!"
+        );
+        assert_eq!(resolve(r#"${if charge >= "10" then "ok"}"#), "");
+        assert_eq!(resolve(r#"${if missing then "set" else "unset"}"#), "unset");
+        assert_eq!(
+            resolve(r#"<${if status != "Full" then "${status|eq:Charging?+:-}"}>"#),
+            "<+>"
+        );
+        assert!(Placeholder::new(r#"${if status = "x" then "y"}"#).is_err());
+        assert!(Placeholder::new(r#"${if status then}"#).is_err());
+        assert_eq!(
+            Placeholder::new(r#"${if status == "Charging" then "${charge}" else icon}"#)
+                .unwrap()
+                .var_names(),
+            vec!["status", "charge", "icon"]
+        );
+    }
+
+    #[test]
+    fn test_numeric_filters() {
+        let mut map = HashMap::new();
+        map.insert("used".into(), "1610612736".into());
+        map.insert("ratio".into(), "0.4567".into());
+        map.insert("position".into(), "3725".into());
+        map.insert("text".into(), "n/a".into());
+        let resolve = |expr: &str| Placeholder::new(expr).unwrap().resolve(&map).unwrap();
+        assert_eq!(resolve("${ratio|mul:100|round:1}%"), "45.7%");
+        assert_eq!(resolve("${ratio|mul:100|fmt:.2}"), "45.67");
+        assert_eq!(resolve("${ratio|mul:100|fmt:06.1}"), "0045.7");
+        assert_eq!(resolve("${ratio|add:1|fmt:5}"), "1.4567");
+        assert_eq!(resolve("${used|div:1024|div:1024}"), "1536");
+        assert_eq!(resolve("${used|bytes}"), "1.5 GiB");
+        assert_eq!(resolve("${used|bytes_si}"), "1.6 GB");
+        assert_eq!(resolve("${position|duration}"), "1:02:05");
+        assert_eq!(resolve("${text|mul:2}"), "n/a");
+        assert_eq!(resolve("${missing|bytes}"), "");
+        assert!(Placeholder::new("${used|div:0}").is_err());
+        assert!(Placeholder::new("${used|mul:x}").is_err());
+    }
+
+    #[test]
+    fn test_string_filters() {
+        let mut map = HashMap::new();
+        map.insert("title".into(), "  the QUICK <fox> & 'dog'  ".into());
+        map.insert("time".into(), "12:34:56".into());
+        map.insert("path".into(), "/usr/share/icons".into());
+        let resolve = |expr: &str| Placeholder::new(expr).unwrap().resolve(&map).unwrap();
+        assert_eq!(resolve("${title|trim|upper}"), "THE QUICK <FOX> & 'DOG'");
+        assert_eq!(resolve("${title|trim|lower}"), "the quick <fox> & 'dog'");
+        assert_eq!(resolve("${title|trim|title}"), "The Quick <Fox> & 'Dog'");
+        assert_eq!(
+            resolve("<b>${title|trim|pango_escape}</b>"),
+            "<b>the QUICK &lt;fox&gt; &amp; &#39;dog&#39;</b>"
+        );
+        assert_eq!(
+            resolve("${time|replace:(\\d+):(\\d+):\\d+:$1.$2 h}"),
+            "12.34 h"
+        );
+        assert_eq!(resolve("${time|split:::1}"), "34");
+        assert_eq!(resolve("${path|split:/:-1}"), "icons");
+        assert_eq!(resolve("${path|split:/:9}"), "");
+        assert!(Placeholder::new("${time|replace:(:x}").is_err());
+        assert!(Placeholder::new("${time|split:1}").is_err());
+    }
+
+    #[test]
+    fn test_nested() {
+        let mut map = HashMap::new();
+        map.insert("fallback".into(), "none".into());
+        map.insert("width".into(), "6".into());
+        map.insert("active_iface".into(), "wlan0".into());
+        map.insert("wlan0.ipv4_0_addr".into(), "10.0.0.2".into());
+        map.insert("stats:net.wlan0.ipv4_0_addr".into(), "10.0.0.3".into());
+        let resolve = |expr: &str| Placeholder::new(expr).unwrap().resolve(&map).unwrap();
+        assert_eq!(resolve("${missing|def:${fallback}}"), "none");
+        assert_eq!(resolve("${missing|def:${other|def:x}!}"), "x!");
+        assert_eq!(resolve("${fallback|align:_>${width}}"), "__none");
+        assert_eq!(resolve("${@{active_iface}.ipv4_0_addr}"), "10.0.0.2");
+        assert_eq!(
+            resolve("${stats:net.@{active_iface}.ipv4_0_addr|def:-}"),
+            "10.0.0.3"
+        );
+        assert_eq!(resolve("${@{missing}.ipv4_0_addr|def:-}"), "-");
+        assert!(Placeholder::new("${@{active_iface.ipv4_0_addr}").is_err());
+        assert_eq!(
+            Placeholder::new("${stats:net.@{active_iface}.ipv4|def:${fallback}}")
+                .unwrap()
+                .var_names(),
+            vec!["active_iface", "fallback"]
+        );
+    }
 }
@@ -0,0 +1,256 @@
+// Copyright 2023 Oatbar Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::{config, parse, state, thread};
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Which vars are saved, taken from the config.
+#[derive(Default)]
+struct Settings {
+    enabled: bool,
+    patterns: Vec<config::Regex>,
+    /// `[[var]]`s are computed from other vars and are not saved.
+    computed: HashSet<String>,
+}
+
+impl Settings {
+    fn new(config: &config::Config<parse::Placeholder>) -> Self {
+        Self {
+            enabled: config.persist || !config.persist_vars.is_empty(),
+            patterns: config.persist_vars.clone(),
+            computed: config.vars.keys().cloned().collect(),
+        }
+    }
+
+    fn saved(&self, name: &str) -> bool {
+        if !self.enabled
+            || self.computed.contains(name)
+            || name.starts_with("theme.")
+            || name.starts_with("_internal:")
+            || name
+                .split_once(':')
+                .is_some_and(|(_, var)| var.starts_with("__"))
+        {
+            return false;
+        }
+        self.patterns.is_empty() || self.patterns.iter().any(|p| p.is_match(name))
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    settings: Settings,
+    vars: BTreeMap<String, String>,
+    dirty: bool,
+}
+
+/// Saves vars to a file to restore them after a restart.
+#[derive(Clone)]
+pub struct Persister {
+    path: PathBuf,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Persister {
+    pub fn new(
+        instance_name: &str,
+        config: &config::Config<parse::Placeholder>,
+    ) -> anyhow::Result<Self> {
+        let mut path = dirs::state_dir()
+            .or_else(dirs::cache_dir)
+            .context("Unable to get state dir")?;
+        path.push(format!("oatbar/{}.vars.json", instance_name));
+        Ok(Self {
+            path,
+            inner: Arc::new(Mutex::new(Inner {
+                settings: Settings::new(config),
+                ..Default::default()
+            })),
+        })
+    }
+
+    pub fn configure(&self, config: &config::Config<parse::Placeholder>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.settings = Settings::new(config);
+        inner.dirty = true;
+    }
+
+    /// Update setting the saved vars, empty if persistence is disabled.
+    pub fn restore(&self) -> state::VarUpdate {
+        let inner = self.inner.lock().unwrap();
+        let mut var_update = state::VarUpdate::default();
+        if !inner.settings.enabled {
+            return var_update;
+        }
+        let vars: BTreeMap<String, String> = match std::fs::read(&self.path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(vars) => vars,
+                Err(e) => {
+                    tracing::warn!("Ignoring saved vars in {:?}: {:?}", self.path, e);
+                    return var_update;
+                }
+            },
+            Err(_) => return var_update,
+        };
+        tracing::info!("Restoring {} vars from {:?}", vars.len(), self.path);
+        var_update.entries = vars
+            .into_iter()
+            .filter(|(name, _)| inner.settings.saved(name))
+            .map(|(var, value)| state::UpdateEntry {
+                var,
+                value,
+                ..Default::default()
+            })
+            .collect();
+        var_update
+    }
+
+    /// Writes the vars if they changed since the last save.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.dirty || !inner.settings.enabled {
+            return Ok(());
+        }
+        let vars: BTreeMap<&String, &String> = inner
+            .vars
+            .iter()
+            .filter(|(name, value)| !value.is_empty() && inner.settings.saved(name))
+            .collect();
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write and rename, so that a crash does not leave a partial file.
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&vars)?)
+            .with_context(|| format!("Unable to write {:?}", tmp_path))?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        inner.dirty = false;
+        Ok(())
+    }
+
+    /// Follows var updates and saves them every `SAVE_INTERVAL`.
+    pub fn spawn(
+        &self,
+        var_snapshot_updates_rx: crossbeam_channel::Receiver<state::VarSnapshotUpdate>,
+    ) -> anyhow::Result<()> {
+        let persister = self.clone();
+        thread::spawn("persist", move || loop {
+            let tick = crossbeam_channel::after(SAVE_INTERVAL);
+            loop {
+                crossbeam_channel::select! {
+                    recv(var_snapshot_updates_rx) -> update => {
+                        let Ok(update) = update else {
+                            return persister.save();
+                        };
+                        let mut inner = persister.inner.lock().unwrap();
+                        inner.vars.extend(update.vars);
+                        inner.dirty = true;
+                    }
+                    recv(tick) -> _ => break,
+                }
+            }
+            if let Err(e) = persister.save() {
+                tracing::warn!("Failed to save vars: {:?}", e);
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Saves the vars and exits when oatbar gets SIGTERM or SIGINT.
+    pub fn spawn_signal_handler(&self) -> anyhow::Result<()> {
+        let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT])?;
+        let persister = self.clone();
+        thread::spawn("signals", move || {
+            if persister.save_on_signal(signals.forever()).is_some() {
+                std::process::exit(0);
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Waits for the first of `signals` and saves the vars.
+    fn save_on_signal(&self, mut signals: impl Iterator<Item = i32>) -> Option<i32> {
+        let signal = signals.next()?;
+        tracing::info!("Terminating via signal {}.", signal);
+        if let Err(e) = self.save() {
+            tracing::error!("Failed to save vars: {:?}", e);
+        }
+        Some(signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(persist: bool, persist_vars: &str) -> Settings {
+        let mut config = config::Config::<parse::Placeholder> {
+            persist,
+            persist_vars: serde_json::from_str(persist_vars).unwrap(),
+            ..Default::default()
+        };
+        config.vars.insert("computed".into(), Default::default());
+        Settings::new(&config)
+    }
+
+    #[test]
+    fn test_saved() {
+        let disabled = settings(false, "[]");
+        assert!(!disabled.saved("dnd"));
+
+        let all = settings(true, "[]");
+        assert!(all.saved("dnd"));
+        assert!(all.saved("weather:value"));
+        assert!(!all.saved("computed"));
+        assert!(!all.saved("theme.foreground"));
+        assert!(!all.saved("weather:__stale"));
+        assert!(!all.saved("_internal:popup.clock"));
+
+        let matching = settings(false, r#"["^dnd$", "^project"]"#);
+        assert!(matching.saved("dnd"));
+        assert!(matching.saved("project_name"));
+        assert!(!matching.saved("weather:value"));
+    }
+
+    #[test]
+    fn test_save_on_signal() {
+        let dir = crate::test_util::temp_dir("persist-signal");
+        let persister = Persister {
+            path: dir.join("oatbar.vars.json"),
+            inner: Arc::new(Mutex::new(Inner {
+                settings: settings(true, "[]"),
+                vars: [("dnd".to_string(), "on".to_string())].into(),
+                dirty: true,
+            })),
+        };
+        let signal = signal_hook::consts::SIGUSR1;
+        let mut signals = signal_hook::iterator::Signals::new([signal]).unwrap();
+        signal_hook::low_level::raise(signal).unwrap();
+        assert_eq!(persister.save_on_signal(signals.forever()), Some(signal));
+        assert_eq!(
+            std::fs::read_to_string(&persister.path).unwrap(),
+            r#"{"dnd":"on"}"#
+        );
+    }
+}
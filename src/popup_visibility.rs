@@ -177,6 +177,10 @@ impl PopupManager {
                 value: "true".into(),
             }],
             error: None,
+            snapshot: false,
+            stale_value: None,
+            i3bar_row: false,
+            keep_error: false,
         }))
     }
 
@@ -194,6 +198,10 @@ impl PopupManager {
                 value: "".into(),
             }],
             error: None,
+            snapshot: false,
+            stale_value: None,
+            i3bar_row: false,
+            keep_error: false,
         })
     }
 
@@ -5,14 +5,45 @@ pub mod i3bar {
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Header {
         pub version: i32,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub click_events: bool,
+        /// Signal stopping the command while its blocks are hidden.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub stop_signal: Option<i32>,
+        /// Signal continuing the command, `SIGCONT` if not set.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub cont_signal: Option<i32>,
     }
 
     impl Default for Header {
         fn default() -> Self {
-            Self { version: 1 }
+            Self {
+                version: 1,
+                click_events: false,
+                stop_signal: None,
+                cont_signal: None,
+            }
         }
     }
 
+    /// Click on a block, sent to commands with `click_events` in their header.
+    // Only `oatbar` itself sends clicks, other binaries include this module too.
+    #[allow(dead_code)]
+    #[derive(Clone, Default, Debug, Serialize, Deserialize)]
+    pub struct ClickEvent {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub instance: Option<String>,
+        pub button: u8,
+        pub x: i32,
+        pub y: i32,
+        pub relative_x: i32,
+        pub relative_y: i32,
+        pub width: i32,
+        pub height: i32,
+    }
+
     #[derive(Clone, Default, Debug, Serialize, Deserialize)]
     pub struct Block {
         pub full_text: String,
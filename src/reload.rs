@@ -0,0 +1,104 @@
+// Copyright 2023 Oatbar Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::{clock, config, persist, source, state, thread};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reloads the config file and applies it to the running bar.
+pub struct Reloader {
+    path: PathBuf,
+    sources: Vec<PathBuf>,
+    commands: source::CommandSet,
+    clocks: clock::ClockSet,
+    persister: persist::Persister,
+    update_tx: crossbeam_channel::Sender<state::Update>,
+}
+
+impl Reloader {
+    pub fn new(
+        path: PathBuf,
+        sources: Vec<PathBuf>,
+        commands: source::CommandSet,
+        clocks: clock::ClockSet,
+        persister: persist::Persister,
+        update_tx: crossbeam_channel::Sender<state::Update>,
+    ) -> Self {
+        Self {
+            path,
+            sources,
+            commands,
+            clocks,
+            persister,
+            update_tx,
+        }
+    }
+
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        tracing::info!("Reloading config from {:?}", self.path);
+        let config = match config::load_from(&self.path) {
+            Ok(config) => config,
+            Err(e) => {
+                // Keep the current config running, just surface the problem.
+                self.update_tx
+                    .send(state::Update::ConfigError(format!("{:?}", e)))?;
+                return Err(e);
+            }
+        };
+        let commands = config.commands.clone();
+        let clocks = config.clocks.clone();
+        self.sources = config.sources.clone();
+        self.persister.configure(&config);
+        self.update_tx
+            .send(state::Update::Reload(Box::new(config)))?;
+        self.commands.apply(commands)?;
+        self.clocks.apply(clocks)?;
+        Ok(())
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        self.sources.clone()
+    }
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Polls the config files and reloads the bar when any of them changes.
+pub fn watch(reloader: Arc<Mutex<Reloader>>) -> anyhow::Result<()> {
+    let mut files = reloader.lock().unwrap().watched_files();
+    let mut last_modified = modified_times(&files);
+    thread::spawn_loop("config-watch", move || {
+        std::thread::sleep(WATCH_INTERVAL);
+        let modified = modified_times(&files);
+        if modified == last_modified {
+            return Ok(true);
+        }
+        let mut reloader = reloader.lock().unwrap();
+        if let Err(e) = reloader.reload() {
+            tracing::error!("Failed to reload config: {:?}", e);
+        }
+        files = reloader.watched_files();
+        last_modified = modified_times(&files);
+        Ok(true)
+    })
+}
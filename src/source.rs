@@ -17,15 +17,18 @@ use anyhow::Context;
 use crossbeam_channel::select;
 use serde::de::*;
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::io::{BufRead, BufReader};
-use std::time::Duration;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::{state, thread};
+use crate::{state, thread, timer};
 
 #[derive(Clone)]
 pub struct Poker {
-    tx: std::collections::HashMap<String, crossbeam_channel::Sender<()>>,
+    tx: Arc<Mutex<HashMap<String, crossbeam_channel::Sender<()>>>>,
 }
 
 impl Poker {
@@ -35,30 +38,250 @@ impl Poker {
         }
     }
 
-    pub fn add(&mut self, name: String) -> crossbeam_channel::Receiver<()> {
+    pub fn add(&self, name: String) -> crossbeam_channel::Receiver<()> {
         let (tx, rx) = crossbeam_channel::unbounded();
-        self.tx.insert(name, tx);
+        self.tx.lock().unwrap().insert(name, tx);
         rx
     }
 
+    pub fn remove(&self, name: &str) {
+        self.tx.lock().unwrap().remove(name);
+    }
+
     pub fn poke(&self, name: Option<String>) {
+        let tx = self.tx.lock().unwrap();
         if let Some(name) = name {
-            if let Some(tx) = self.tx.get(&name) {
+            if let Some(tx) = tx.get(&name) {
                 let _ = tx.send(());
             } else {
                 tracing::warn!("Command {:?} not found during poking", name);
             }
         } else {
-            for tx in self.tx.values() {
+            for tx in tx.values() {
                 let _ = tx.send(());
             }
         }
     }
 }
 
+/// Clicks waiting to be written to a command, newer ones are dropped.
+const MAX_PENDING_CLICKS: usize = 64;
+
+/// Blocks of a command with `click_events` in its i3bar header.
+struct ClickTarget {
+    /// Lines written to the stdin of the command.
+    tx: crossbeam_channel::Sender<String>,
+    /// Whether an event was sent already, the events form a JSON array.
+    started: bool,
+    /// Var prefixes of the blocks in the last row, with their name and instance.
+    blocks: Vec<(String, Option<String>, Option<String>)>,
+}
+
+/// Sends clicks on blocks to the i3bar commands that produce their vars.
+#[derive(Clone, Default)]
+pub struct Clicks {
+    targets: Arc<Mutex<HashMap<String, ClickTarget>>>,
+}
+
+impl Clicks {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn register(
+        &self,
+        command_name: &str,
+        mut stdin: std::process::ChildStdin,
+    ) -> anyhow::Result<()> {
+        let (tx, rx) = crossbeam_channel::bounded::<String>(MAX_PENDING_CLICKS);
+        // A command not reading its stdin must not block the bar.
+        thread::spawn(format!("{}-clicks", command_name), move || {
+            stdin.write_all(b"[\n")?;
+            for line in rx {
+                stdin.write_all(line.as_bytes())?;
+            }
+            Ok(())
+        })?;
+        self.targets.lock().unwrap().insert(
+            command_name.into(),
+            ClickTarget {
+                tx,
+                started: false,
+                blocks: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    fn set_blocks(&self, command_name: &str, row: &[i3bar::Block]) {
+        if let Some(target) = self.targets.lock().unwrap().get_mut(command_name) {
+            target.blocks = row
+                .iter()
+                .enumerate()
+                .map(|(idx, block)| {
+                    (
+                        format!("{}.", block_key(idx, block)),
+                        block.name.clone(),
+                        block.instance.clone(),
+                    )
+                })
+                .collect();
+        }
+    }
+
+    fn remove(&self, command_name: &str) {
+        self.targets.lock().unwrap().remove(command_name);
+    }
+
+    /// Sends the click to the command producing the first of `vars` that comes from an i3bar block.
+    pub fn send(&self, vars: &[String], event: i3bar::ClickEvent) -> anyhow::Result<()> {
+        let mut targets = self.targets.lock().unwrap();
+        for var in vars {
+            let Some((command_name, var)) = var.split_once(':') else {
+                continue;
+            };
+            let Some(target) = targets.get_mut(command_name) else {
+                continue;
+            };
+            let Some((_, name, instance)) = target
+                .blocks
+                .iter()
+                .filter(|(prefix, _, _)| var.starts_with(prefix))
+                .max_by_key(|(prefix, _, _)| prefix.len())
+            else {
+                continue;
+            };
+            let event = i3bar::ClickEvent {
+                name: name.clone(),
+                instance: instance.clone(),
+                ..event
+            };
+            let separator = if target.started { "," } else { "" };
+            let line = format!("{}{}\n", separator, serde_json::to_string(&event)?);
+            match target.tx.try_send(line) {
+                Ok(()) => target.started = true,
+                Err(crossbeam_channel::TrySendError::Full(_)) => {
+                    tracing::warn!("Command {} is not reading clicks, dropping", command_name);
+                }
+                Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
+                    tracing::warn!("Command {} stopped reading clicks", command_name);
+                    targets.remove(command_name);
+                }
+            }
+            return Ok(());
+        }
+        Ok(())
+    }
+}
+
+/// Pauses i3bar commands with a `stop_signal` while no visible block needs their vars.
+#[derive(Clone, Default)]
+pub struct Stopper {
+    inner: Arc<Mutex<StopperInner>>,
+}
+
+#[derive(Default)]
+struct StopperInner {
+    commands: HashMap<String, CommandHandle>,
+    /// Commands needed by each bar, `None` until the bar reports them.
+    bars: HashMap<usize, Option<HashSet<String>>>,
+    next_bar_id: usize,
+}
+
+impl StopperInner {
+    fn apply(&self) {
+        // Nothing is paused until every bar knows what it shows.
+        let reported = !self.bars.is_empty() && self.bars.values().all(Option::is_some);
+        for (name, handle) in self.commands.iter() {
+            let needed = self
+                .bars
+                .values()
+                .flatten()
+                .any(|needed| needed.contains(name));
+            handle.pause(reported && !needed);
+        }
+    }
+}
+
+impl Stopper {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn set_commands(&self, commands: HashMap<String, CommandHandle>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.commands = commands;
+        inner.apply();
+    }
+
+    pub fn add_bar(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_bar_id;
+        inner.next_bar_id += 1;
+        inner.bars.insert(id, None);
+        id
+    }
+
+    pub fn remove_bar(&self, id: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.bars.remove(&id);
+        inner.apply();
+    }
+
+    /// Sets the commands that the bar shows or that decide what it shows.
+    pub fn set_needed(&self, id: usize, needed: HashSet<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.bars.insert(id, Some(needed));
+        inner.apply();
+    }
+}
+
 struct RowVisitor {
     tx: crossbeam_channel::Sender<state::Update>,
     command_name: String,
+    handle: CommandHandle,
+    clicks: Clicks,
+}
+
+/// Entry resetting `__stale` once a stale command produces output again.
+fn fresh_entry() -> state::UpdateEntry {
+    state::UpdateEntry {
+        var: "__stale".into(),
+        ..Default::default()
+    }
+}
+
+/// Prefix of the vars of the block, `<name>` or `<name>.<instance>`.
+fn block_key(idx: usize, block: &i3bar::Block) -> String {
+    let name = block.name.clone().unwrap_or_else(|| format!("{}", idx));
+    match &block.instance {
+        Some(instance) => format!("{}.{}", name, instance),
+        None => name,
+    }
+}
+
+/// Joins block keys with `,` for `__blocks`, escaping `,` and `\` in them.
+fn join_block_keys(keys: impl Iterator<Item = String>) -> String {
+    keys.map(|key| key.replace('\\', "\\\\").replace(',', "\\,"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Splits `__blocks` into block keys.
+pub fn split_block_keys(blocks: &str) -> Vec<String> {
+    let mut keys = vec![];
+    let mut key = String::new();
+    let mut chars = blocks.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => key.extend(chars.next()),
+            ',' => keys.push(std::mem::take(&mut key)),
+            ch => key.push(ch),
+        }
+    }
+    keys.push(key);
+    keys.retain(|key| !key.is_empty());
+    keys
 }
 
 pub fn block_to_su_entry(idx: usize, block: i3bar::Block) -> Vec<state::UpdateEntry> {
@@ -101,15 +324,31 @@ impl<'de> Visitor<'de> for RowVisitor {
         A: SeqAccess<'de>,
     {
         while let Some(row) = seq.next_element::<Vec<i3bar::Block>>()? {
-            let entries = row
+            self.clicks.set_blocks(&self.command_name, &row);
+            // The order of the blocks, used by `type = "i3bar"` blocks.
+            let order = state::UpdateEntry {
+                var: "__blocks".into(),
+                value: join_block_keys(
+                    row.iter()
+                        .enumerate()
+                        .map(|(idx, block)| block_key(idx, block)),
+                ),
+                ..Default::default()
+            };
+            let mut entries: Vec<_> = row
                 .into_iter()
                 .enumerate()
                 .flat_map(|(idx, block)| block_to_su_entry(idx, block))
                 .collect();
+            entries.push(order);
+            if self.handle.touch() {
+                entries.push(fresh_entry());
+            }
             self.tx
                 .send(state::Update::VarUpdate(state::VarUpdate {
                     command_name: Some(self.command_name.clone()),
                     entries,
+                    i3bar_row: true,
                     ..Default::default()
                 }))
                 .unwrap();
@@ -121,6 +360,7 @@ impl<'de> Visitor<'de> for RowVisitor {
 struct PlainSender {
     command_name: String,
     tx: crossbeam_channel::Sender<state::Update>,
+    handle: CommandHandle,
     line_names: Vec<String>, // has at least 1 element.
     entries: Vec<state::UpdateEntry>,
 }
@@ -129,6 +369,7 @@ impl PlainSender {
     fn new(
         command_name: &str,
         tx: crossbeam_channel::Sender<state::Update>,
+        handle: CommandHandle,
         line_names: Vec<String>,
     ) -> Self {
         let line_names = if line_names.is_empty() {
@@ -140,6 +381,7 @@ impl PlainSender {
         Self {
             command_name: command_name.into(),
             tx,
+            handle,
             line_names,
             entries,
         }
@@ -152,8 +394,11 @@ impl PlainSender {
             ..Default::default()
         });
         if self.entries.len() == self.line_names.len() {
-            let entries =
+            let mut entries =
                 std::mem::replace(&mut self.entries, Vec::with_capacity(self.line_names.len()));
+            if self.handle.touch() {
+                entries.push(fresh_entry());
+            }
             self.tx.send(state::Update::VarUpdate(state::VarUpdate {
                 command_name: Some(self.command_name.clone()),
                 entries,
@@ -165,6 +410,7 @@ impl PlainSender {
 }
 
 #[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum Format {
     Auto,
@@ -172,7 +418,18 @@ pub enum Format {
     I3bar,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// When a command is run again after it exits.
+#[derive(Debug, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Always,
+    OnFailure,
+    Never,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CommandConfig {
     name: Option<String>,
     command: String,
@@ -183,23 +440,271 @@ pub struct CommandConfig {
     line_names: Vec<String>,
     #[serde(default)]
     once: bool,
+    /// Seconds without output after which the vars of the command are stale.
+    ttl: Option<u64>,
+    /// Value of the vars of a stale command, they are removed if not set.
+    stale_value: Option<String>,
+    /// `always` by default, `never` with `once`.
+    restart: Option<RestartPolicy>,
+    /// Failures in a row after which the command is not restarted anymore.
+    max_restarts: Option<u32>,
+    /// Limit in seconds of the delay before a restart, which doubles with every failure.
+    max_backoff: Option<u64>,
+    /// Seconds without output after which a running command and its children are killed.
+    timeout: Option<u64>,
 }
 
 fn default_format() -> Format {
     Format::Auto
 }
 
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Time between SIGTERM and SIGKILL for a command that timed out.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl CommandConfig {
+    /// `name`, or `cm<index>` for the command at `index` in the config.
+    pub fn name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("cm{}", index))
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        match self.restart {
+            Some(restart) => restart,
+            None if self.once => RestartPolicy::Never,
+            None => RestartPolicy::Always,
+        }
+    }
+
+    /// Whether the command runs again after `failures` failed runs in a row.
+    fn restarts(&self, failures: u32) -> bool {
+        let policy_allows = match self.restart_policy() {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failures > 0,
+            RestartPolicy::Never => false,
+        };
+        policy_allows && self.max_restarts.is_none_or(|max| failures <= max)
+    }
+
+    /// `interval`, doubled for every failed run in a row up to `max_backoff`.
+    fn restart_delay(&self, failures: u32) -> Duration {
+        let interval = self.interval.map_or(DEFAULT_INTERVAL, Duration::from_secs);
+        if failures == 0 {
+            return interval;
+        }
+        let max_backoff = self
+            .max_backoff
+            .map_or(DEFAULT_MAX_BACKOFF, Duration::from_secs)
+            .max(interval);
+        interval
+            .max(Duration::from_secs(1))
+            .saturating_mul(2u32.saturating_pow(failures - 1))
+            .min(max_backoff)
+    }
+}
+
 pub struct Command {
     pub index: usize,
     pub config: CommandConfig,
 }
 
+/// Shared handle to a spawned command, used to stop it when it is no longer configured.
+#[derive(Clone, Default)]
+pub struct CommandHandle {
+    stopped: Arc<AtomicBool>,
+    pid: Arc<Mutex<Option<u32>>>,
+    signals: Arc<Mutex<Option<Signals>>>,
+    freshness: Arc<Mutex<Freshness>>,
+}
+
+/// `stop_signal` and `cont_signal` from the i3bar header of the running child.
+struct Signals {
+    stop: i32,
+    cont: i32,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Freshness {
+    last_output: Option<Instant>,
+    stale: bool,
+    /// Set while the child is paused by its `stop_signal`, which stops the clock.
+    paused: bool,
+}
+
+impl CommandHandle {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Remembers the running child, returns false if the command was stopped already.
+    fn track(&self, child: &std::process::Child) -> bool {
+        let mut pid = self.pid.lock().unwrap();
+        if self.is_stopped() {
+            return false;
+        }
+        *pid = Some(child.id());
+        true
+    }
+
+    /// Records output of the command, returns true if its vars were stale.
+    fn touch(&self) -> bool {
+        let mut freshness = self.freshness.lock().unwrap();
+        freshness.last_output = Some(Instant::now());
+        std::mem::take(&mut freshness.stale)
+    }
+
+    /// Time since the last output, or since `start` if there was none after it.
+    ///
+    /// Zero while the child is paused.
+    fn idle(&self, start: Instant) -> Duration {
+        let freshness = self.freshness.lock().unwrap();
+        if freshness.paused {
+            return Duration::ZERO;
+        }
+        freshness
+            .last_output
+            .map_or(start, |last_output| last_output.max(start))
+            .elapsed()
+    }
+
+    fn untrack(&self) {
+        let mut pid = self.pid.lock().unwrap();
+        *pid = None;
+        *self.signals.lock().unwrap() = None;
+        // The next child starts running, even if this one exited while paused.
+        self.freshness.lock().unwrap().paused = false;
+    }
+
+    /// Allows pausing the running child if its i3bar header sets `stop_signal`.
+    fn set_signals(&self, header: &i3bar::Header) {
+        let Some(stop) = header.stop_signal.filter(|signal| *signal > 0) else {
+            return;
+        };
+        *self.signals.lock().unwrap() = Some(Signals {
+            stop,
+            cont: header.cont_signal.unwrap_or(libc::SIGCONT),
+            stopped: false,
+        });
+    }
+
+    /// Sends the stop or the continue signal, unless the child is paused that way already.
+    fn pause(&self, paused: bool) {
+        let pid = self.pid.lock().unwrap();
+        let mut signals = self.signals.lock().unwrap();
+        let (Some(pid), Some(signals)) = (*pid, signals.as_mut()) else {
+            return;
+        };
+        if signals.stopped == paused {
+            return;
+        }
+        signals.stopped = paused;
+        {
+            // A paused child has no output, the clock restarts once it continues.
+            let mut freshness = self.freshness.lock().unwrap();
+            freshness.paused = paused;
+            if !paused {
+                freshness.last_output = Some(Instant::now());
+            }
+        }
+        let signal = if paused { signals.stop } else { signals.cont };
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+
+    pub fn stop(&self) {
+        let pid = self.pid.lock().unwrap();
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(pid) = *pid {
+            unsafe {
+                libc::killpg(pid as libc::pid_t, libc::SIGTERM);
+            }
+            // A stopped child handles SIGTERM only once it continues.
+            if self
+                .signals
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|s| s.stopped)
+            {
+                unsafe {
+                    libc::killpg(pid as libc::pid_t, libc::SIGCONT);
+                }
+            }
+        }
+    }
+}
+
+/// Terminates the process group of a child without output for longer than the timeout,
+/// until dropped.
+struct Watchdog {
+    _done: crossbeam_channel::Sender<()>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn spawn(
+        command_name: &str,
+        pid: u32,
+        timeout: Duration,
+        handle: CommandHandle,
+    ) -> anyhow::Result<Self> {
+        use crossbeam_channel::RecvTimeoutError;
+        let (done_tx, done_rx) = crossbeam_channel::bounded::<()>(0);
+        let timed_out = Arc::new(AtomicBool::new(false));
+        {
+            let timed_out = timed_out.clone();
+            let command_name = command_name.to_string();
+            thread::spawn(format!("{}-timeout", command_name), move || {
+                let started = Instant::now();
+                let mut remaining = timeout;
+                loop {
+                    if done_rx.recv_timeout(remaining) != Err(RecvTimeoutError::Timeout) {
+                        return Ok(());
+                    }
+                    // Every output of a streaming command re-arms the timeout.
+                    match timeout.checked_sub(handle.idle(started)) {
+                        Some(left) if !left.is_zero() => remaining = left,
+                        _ => break,
+                    }
+                }
+                tracing::warn!("Command {} timed out, terminating", command_name);
+                timed_out.store(true, Ordering::SeqCst);
+                unsafe {
+                    libc::killpg(pid as libc::pid_t, libc::SIGTERM);
+                }
+                if done_rx.recv_timeout(KILL_TIMEOUT) == Err(RecvTimeoutError::Timeout) {
+                    tracing::warn!("Command {} did not terminate, killing", command_name);
+                    unsafe {
+                        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(Self {
+            _done: done_tx,
+            timed_out,
+        })
+    }
+
+    fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+}
+
+/// How a run of the command ended.
+struct Exit {
+    status: std::process::ExitStatus,
+    /// The command was killed after `timeout`.
+    timed_out: bool,
+}
+
 impl Command {
     pub fn name(&self) -> String {
-        self.config
-            .name
-            .clone()
-            .unwrap_or_else(|| format!("cm{}", self.index))
+        self.config.name(self.index)
     }
 }
 
@@ -208,7 +713,9 @@ impl Command {
         &self,
         command_name: &str,
         tx: &crossbeam_channel::Sender<state::Update>,
-    ) -> anyhow::Result<()> {
+        handle: &CommandHandle,
+        clicks: &Clicks,
+    ) -> anyhow::Result<Exit> {
         use std::os::unix::process::CommandExt;
         let mut child = std::process::Command::new("sh");
         child
@@ -216,6 +723,10 @@ impl Command {
             .arg(format!("exec {}", &self.config.command))
             .stderr(std::process::Stdio::inherit())
             .stdout(std::process::Stdio::piped());
+        if self.config.format != Format::Plain {
+            // Click events are written to stdin if the i3bar header asks for them.
+            child.stdin(std::process::Stdio::piped());
+        }
 
         #[cfg(target_os = "linux")]
         unsafe {
@@ -224,38 +735,61 @@ impl Command {
                 Ok(())
             });
         }
+        // A process group of its own, so that stopping or a timeout kills the children too.
+        child.process_group(0);
+        let timeout = self.config.timeout.map(Duration::from_secs);
 
         let mut child = child.spawn().context("Failed spawning")?;
-        if let Err(e) = self.process_child_output(command_name, &mut child, tx.clone()) {
-            return Err(anyhow::anyhow!("Error running command: {:?}", e));
+        if !handle.track(&child) {
+            let _ = child.kill();
+            return Ok(Exit {
+                status: child.wait()?,
+                timed_out: false,
+            });
         }
-        let result = child.wait()?;
-        if !result.success() {
-            if let Some(code) = result.code() {
-                return Err(anyhow::anyhow!("command exit code {:?}", code));
-            } else {
-                return Err(anyhow::anyhow!(
-                    "command exit code unknown, result: {:?}",
-                    result
-                ));
-            }
+        let watchdog = match timeout {
+            Some(timeout) => Some(Watchdog::spawn(
+                command_name,
+                child.id(),
+                timeout,
+                handle.clone(),
+            )?),
+            None => None,
+        };
+        let output =
+            self.process_child_output(command_name, &mut child, tx.clone(), handle, clicks);
+        clicks.remove(command_name);
+        if let Err(e) = output {
+            handle.untrack();
+            return Err(anyhow::anyhow!("Error running command: {:?}", e));
         }
-        Ok(())
+        let result = child.wait();
+        handle.untrack();
+        Ok(Exit {
+            status: result?,
+            timed_out: watchdog.is_some_and(|watchdog| watchdog.timed_out()),
+        })
     }
-
     fn process_child_output(
         &self,
         command_name: &str,
         child: &mut std::process::Child,
         tx: crossbeam_channel::Sender<state::Update>,
+        handle: &CommandHandle,
+        clicks: &Clicks,
     ) -> anyhow::Result<()> {
+        let mut stdin = child.stdin.take();
         let stdout = child.stdout.take().unwrap();
         let mut reader = BufReader::new(stdout);
 
         let mut format = self.config.format.clone();
 
-        let mut plain_sender =
-            PlainSender::new(command_name, tx.clone(), self.config.line_names.clone());
+        let mut plain_sender = PlainSender::new(
+            command_name,
+            tx.clone(),
+            handle.clone(),
+            self.config.line_names.clone(),
+        );
 
         if format == Format::Auto || format == Format::I3bar {
             let mut first_line = String::new();
@@ -269,6 +803,10 @@ impl Command {
                         ));
                     }
                     format = Format::I3bar;
+                    handle.set_signals(&header);
+                    if let (true, Some(stdin)) = (header.click_events, stdin.take()) {
+                        clicks.register(command_name, stdin)?;
+                    }
                 }
                 Err(e) => {
                     if format == Format::I3bar {
@@ -286,12 +824,16 @@ impl Command {
                 }
             }
         }
+        // Without click events the command sees the end of its input.
+        drop(stdin);
 
         if format == Format::I3bar {
             let mut stream = serde_json::Deserializer::from_reader(reader);
             stream.deserialize_seq(RowVisitor {
                 tx,
                 command_name: command_name.into(),
+                handle: handle.clone(),
+                clicks: clicks.clone(),
             })?;
             return Ok(());
         }
@@ -308,10 +850,61 @@ impl Command {
         Ok(())
     }
 
+    /// Publishes `__age_secs` and `__stale` every second, replacing or removing
+    /// the vars of the command once there was no output for `ttl`.
+    fn watch_ttl(
+        &self,
+        command_name: &str,
+        ttl: Duration,
+        tx: crossbeam_channel::Sender<state::Update>,
+        handle: CommandHandle,
+    ) -> anyhow::Result<()> {
+        let started = Instant::now();
+        let stale_value = self.config.stale_value.clone();
+        let name = command_name.to_string();
+        let second = || SystemTime::now() + Duration::from_secs(1);
+        timer::Timer::repeating(&format!("{}-ttl", command_name), second(), move || {
+            if handle.is_stopped() {
+                return None;
+            }
+            let (age, stale, became_stale) = {
+                let mut freshness = handle.freshness.lock().unwrap();
+                if freshness.paused {
+                    return Some(second());
+                }
+                let age = freshness.last_output.unwrap_or(started).elapsed();
+                let became_stale = !freshness.stale && age >= ttl;
+                freshness.stale |= became_stale;
+                (age, freshness.stale, became_stale)
+            };
+            let entry = |var: &str, value: String| state::UpdateEntry {
+                var: var.into(),
+                value,
+                ..Default::default()
+            };
+            tx.send(state::Update::VarUpdate(state::VarUpdate {
+                command_name: Some(name.clone()),
+                entries: vec![
+                    entry("__age_secs", age.as_secs().to_string()),
+                    entry("__stale", if stale { "1" } else { "" }.into()),
+                ],
+                snapshot: became_stale,
+                stale_value: stale_value.clone(),
+                keep_error: true,
+                ..Default::default()
+            }))
+            .ok()?;
+            Some(second())
+        })?;
+        Ok(())
+    }
+
     pub fn spawn(
         self,
         tx: crossbeam_channel::Sender<state::Update>,
         poke_rx: crossbeam_channel::Receiver<()>,
+        handle: CommandHandle,
+        clicks: Clicks,
     ) -> anyhow::Result<()> {
         let command_name = self
             .config
@@ -319,24 +912,100 @@ impl Command {
             .clone()
             .unwrap_or_else(|| format!("cm{}", self.index));
 
+        if let Some(ttl) = self.config.ttl {
+            if let Err(e) = self.watch_ttl(
+                &command_name,
+                Duration::from_secs(ttl),
+                tx.clone(),
+                handle.clone(),
+            ) {
+                tx.send(state::Update::VarUpdate(state::VarUpdate {
+                    command_name: Some(command_name.clone()),
+                    error: Some(format!("Spawning ttl timer failed: {:?}", e)),
+                    ..Default::default()
+                }))?;
+            }
+        }
+
         let result = {
             let tx = tx.clone();
             let command_name = command_name.clone();
-            thread::spawn(command_name.clone(), move || loop {
-                let result = self.run_command(&command_name, &tx);
-                if let Err(e) = result {
-                    tx.send(state::Update::VarUpdate(state::VarUpdate {
-                        command_name: Some(command_name.clone()),
-                        error: Some(format!("Command {} failed: {:?}", command_name, e)),
-                        ..Default::default()
-                    }))?;
-                }
-                if self.config.once {
-                    return Ok(());
-                }
-                select! {
-                    recv(poke_rx) -> _ => tracing::info!("Skipping interval for {} command", command_name),
-                    default(Duration::from_secs(self.config.interval.unwrap_or(10))) => (),
+            thread::spawn(command_name.clone(), move || {
+                // Failed runs in a row and the exit code of the last run.
+                let mut failures: u32 = 0;
+                let mut exit_code = None;
+                loop {
+                    let started = Instant::now();
+                    let result = self.run_command(&command_name, &tx, &handle, &clicks);
+                    if handle.is_stopped() {
+                        tracing::info!("Command {} stopped", command_name);
+                        return Ok(());
+                    }
+                    let (error, new_exit_code) = match result {
+                        Ok(Exit {
+                            status,
+                            timed_out: true,
+                        }) => (
+                            Some(format!(
+                                "timed out after {} seconds without output",
+                                self.config.timeout.unwrap_or_default()
+                            )),
+                            exit_code_of(status),
+                        ),
+                        Ok(Exit { status, .. }) => (exit_error(status), exit_code_of(status)),
+                        Err(e) => (Some(format!("failed: {:?}", e)), None),
+                    };
+                    // A daemon that crashes once in a while starts the backoff anew.
+                    let previous_failures = match started.elapsed() {
+                        elapsed if elapsed > self.config.restart_delay(failures) => 0,
+                        _ => failures,
+                    };
+                    let new_failures = match error {
+                        Some(_) => previous_failures + 1,
+                        None => 0,
+                    };
+                    let given_up = error.is_some() && !self.config.restarts(new_failures);
+                    if error.is_some() || new_failures != failures || new_exit_code != exit_code {
+                        let entry = |var: &str, value: String| state::UpdateEntry {
+                            var: var.into(),
+                            value,
+                            ..Default::default()
+                        };
+                        tx.send(state::Update::VarUpdate(state::VarUpdate {
+                            command_name: Some(command_name.clone()),
+                            entries: vec![
+                                entry(
+                                    "__exit_code",
+                                    new_exit_code.map(|c| c.to_string()).unwrap_or_default(),
+                                ),
+                                entry("__restarts", new_failures.to_string()),
+                                entry("__given_up", if given_up { "1" } else { "" }.into()),
+                            ],
+                            error: error.as_ref().map(|error| match given_up {
+                                true => {
+                                    format!("Command {} {}, not restarted", command_name, error)
+                                }
+                                false => format!("Command {} {}", command_name, error),
+                            }),
+                            ..Default::default()
+                        }))?;
+                    }
+                    failures = new_failures;
+                    exit_code = new_exit_code;
+                    if !self.config.restarts(failures) {
+                        if given_up {
+                            tracing::warn!(
+                                "Command {} is not restarted after {} failures",
+                                command_name,
+                                failures
+                            );
+                        }
+                        return Ok(());
+                    }
+                    select! {
+                        recv(poke_rx) -> _ => tracing::info!("Skipping interval for {} command", command_name),
+                        default(self.config.restart_delay(failures)) => (),
+                    }
                 }
             })
         };
@@ -350,3 +1019,241 @@ impl Command {
         Ok(())
     }
 }
+
+/// Exit code of the command, `128 + signal` if it was killed, like in shells.
+fn exit_code_of(status: std::process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+}
+
+/// Describes how the command failed, `None` if it succeeded.
+fn exit_error(status: std::process::ExitStatus) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+    if status.success() {
+        None
+    } else if let Some(code) = status.code() {
+        Some(format!("exited with code {}", code))
+    } else if let Some(signal) = status.signal() {
+        Some(format!("crashed with signal {}", signal))
+    } else {
+        Some(format!("ended with {:?}", status))
+    }
+}
+
+/// Commands currently running, keyed by command name.
+///
+/// Applying a new list of command configs only restarts commands that
+/// were added or changed, so unchanged commands keep running with their vars intact.
+pub struct CommandSet {
+    tx: crossbeam_channel::Sender<state::Update>,
+    poker: Poker,
+    clicks: Clicks,
+    stopper: Stopper,
+    running: HashMap<String, (CommandConfig, CommandHandle)>,
+}
+
+impl CommandSet {
+    pub fn new(
+        tx: crossbeam_channel::Sender<state::Update>,
+        poker: Poker,
+        clicks: Clicks,
+        stopper: Stopper,
+    ) -> Self {
+        Self {
+            tx,
+            poker,
+            clicks,
+            stopper,
+            running: HashMap::new(),
+        }
+    }
+
+    /// Names of running commands to stop and indices of commands to start for the new config.
+    fn diff(&self, commands: &[Command]) -> (Vec<String>, Vec<usize>) {
+        let mut outdated: Vec<String> = self
+            .running
+            .iter()
+            .filter(|(name, (config, _))| {
+                !commands
+                    .iter()
+                    .any(|c| c.name() == **name && c.config == *config)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        outdated.sort();
+
+        let mut seen = HashSet::new();
+        let started = commands
+            .iter()
+            .filter(|command| {
+                let name = command.name();
+                if !seen.insert(name.clone()) {
+                    tracing::warn!("Duplicate command name {:?}, ignoring", name);
+                    return false;
+                }
+                !self.running.contains_key(&name) || outdated.contains(&name)
+            })
+            .map(|command| command.index)
+            .collect();
+        (outdated, started)
+    }
+
+    pub fn apply(&mut self, configs: Vec<CommandConfig>) -> anyhow::Result<()> {
+        let commands: Vec<Command> = configs
+            .into_iter()
+            .enumerate()
+            .map(|(index, config)| Command { index, config })
+            .collect();
+
+        let (outdated, started) = self.diff(&commands);
+        for name in outdated {
+            if let Some((_, handle)) = self.running.remove(&name) {
+                tracing::info!("Stopping command {}", name);
+                handle.stop();
+                self.poker.remove(&name);
+                if !commands.iter().any(|c| c.name() == name) {
+                    // Drop the vars and errors of the command that is gone.
+                    self.tx.send(state::Update::VarUpdate(state::VarUpdate {
+                        command_name: Some(name),
+                        snapshot: true,
+                        ..Default::default()
+                    }))?;
+                }
+            }
+        }
+
+        for command in commands {
+            if !started.contains(&command.index) {
+                continue;
+            }
+            let name = command.name();
+            tracing::info!("Starting command {}", name);
+            let handle = CommandHandle::default();
+            self.running
+                .insert(name.clone(), (command.config.clone(), handle.clone()));
+            command.spawn(
+                self.tx.clone(),
+                self.poker.add(name),
+                handle,
+                self.clicks.clone(),
+            )?;
+        }
+        self.stopper.set_commands(
+            self.running
+                .iter()
+                .map(|(name, (_, handle))| (name.clone(), handle.clone()))
+                .collect(),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(index: usize, text: &str) -> Command {
+        Command {
+            index,
+            config: toml::from_str(text).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_command_set_diff() {
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        let mut set = CommandSet::new(tx, Poker::new(), Clicks::new(), Stopper::new());
+        for command in [
+            command(0, "name = \"same\"\ncommand = \"date\""),
+            command(1, "name = \"changed\"\ncommand = \"date\""),
+            command(2, "name = \"removed\"\ncommand = \"date\""),
+        ] {
+            set.running
+                .insert(command.name(), (command.config, CommandHandle::default()));
+        }
+        let commands = vec![
+            command(0, "name = \"same\"\ncommand = \"date\""),
+            command(1, "name = \"changed\"\ncommand = \"date\"\ninterval = 5"),
+            command(2, "name = \"added\"\ncommand = \"date\""),
+            command(3, "name = \"added\"\ncommand = \"uptime\""),
+        ];
+        let (outdated, started) = set.diff(&commands);
+        assert_eq!(outdated, vec!["changed", "removed"]);
+        assert_eq!(started, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_command_set_removed() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut set = CommandSet::new(tx, Poker::new(), Clicks::new(), Stopper::new());
+        let removed = command(0, "name = \"removed\"\ncommand = \"date\"");
+        set.running
+            .insert(removed.name(), (removed.config, CommandHandle::default()));
+        set.apply(vec![]).unwrap();
+        let Ok(state::Update::VarUpdate(update)) = rx.try_recv() else {
+            panic!("expected a var update");
+        };
+        assert_eq!(update.command_name.as_deref(), Some("removed"));
+        assert!(update.snapshot);
+        assert!(update.entries.is_empty());
+    }
+
+    #[test]
+    fn test_untrack_unpauses() {
+        let handle = CommandHandle::default();
+        handle.freshness.lock().unwrap().paused = true;
+        let start = Instant::now() - Duration::from_secs(5);
+        assert_eq!(handle.idle(start), Duration::ZERO);
+        handle.untrack();
+        assert!(handle.idle(start) >= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_block_keys() {
+        let keys = vec![
+            "cpu".to_string(),
+            "disk./mnt/a,b".to_string(),
+            "net.C:\\eth0".to_string(),
+        ];
+        let joined = join_block_keys(keys.clone().into_iter());
+        assert_eq!(joined, "cpu,disk./mnt/a\\,b,net.C:\\\\eth0");
+        assert_eq!(split_block_keys(&joined), keys);
+        assert!(split_block_keys("").is_empty());
+    }
+
+    #[test]
+    fn test_restarts() {
+        let once = command(
+            0,
+            "command = \"date\"\nonce = true\nrestart = \"on-failure\"",
+        )
+        .config;
+        assert!(!once.restarts(0));
+        assert!(once.restarts(1));
+
+        let limited = command(0, "command = \"date\"\nmax_restarts = 3").config;
+        assert!(limited.restarts(0));
+        assert!(limited.restarts(3));
+        assert!(!limited.restarts(4));
+
+        let never = command(0, "command = \"date\"\nonce = true").config;
+        assert!(!never.restarts(0));
+        assert!(!never.restarts(1));
+    }
+
+    #[test]
+    fn test_restart_delay() {
+        let config = command(0, "command = \"date\"\ninterval = 5\nmax_backoff = 30").config;
+        let delays: Vec<u64> = (0..6)
+            .map(|failures| config.restart_delay(failures).as_secs())
+            .collect();
+        assert_eq!(delays, vec![5, 5, 10, 20, 30, 30]);
+        assert_eq!(config.restart_delay(100), Duration::from_secs(30));
+
+        let config = command(0, "command = \"date\"").config;
+        assert_eq!(config.restart_delay(0), DEFAULT_INTERVAL);
+        assert_eq!(config.restart_delay(20), DEFAULT_MAX_BACKOFF);
+    }
+}
@@ -18,7 +18,8 @@ use crate::parse;
 
 use anyhow::Context;
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::Instant;
 
 #[derive(Clone, Debug)]
 pub struct ErrorMessage {
@@ -30,12 +31,127 @@ pub struct ErrorMessage {
 pub struct State {
     pub vars: HashMap<String, String>,
     pub error: Option<String>,
+    pub config_error: Option<String>,
     pub command_errors: BTreeMap<String, String>,
     pub var_snapshot_updates_tx: Vec<crossbeam_channel::Sender<VarSnapshotUpdate>>,
     pub pointer_position: HashMap<String, (i16, i16)>,
+    /// Incremented by every update that changes vars.
+    pub version: u64,
+    /// `version` of the last change of each var.
+    var_versions: HashMap<String, u64>,
+    /// `version` before the previous update of `[[var]]`s.
+    previous_vars_version: u64,
+    /// Samples of `[[var]]`s with a `function`, keyed by var name.
+    functions: HashMap<String, FunctionState>,
     config: config::Config<parse::Placeholder>,
 }
 
+/// Samples of the var a `[[var]]` function is computed from.
+#[derive(Clone, Debug, Default)]
+struct FunctionState {
+    samples: VecDeque<(Instant, f64)>,
+    value: String,
+}
+
+impl FunctionState {
+    /// Samples the var if it has arrived and computes the function.
+    ///
+    /// Returns `None` until there are enough samples.
+    fn update(
+        &mut self,
+        function: &config::VarFunction,
+        vars: &HashMap<String, String>,
+        arrived: &HashSet<String>,
+        now: Instant,
+    ) -> Option<f64> {
+        use config::VarFunction;
+        let parse = |value: &String| value.trim().parse::<f64>().ok();
+        let (var, window) = match function {
+            VarFunction::Sum(pattern) => {
+                return Some(
+                    vars.iter()
+                        .filter(|(name, _)| pattern.is_match(name))
+                        .filter_map(|(_, value)| parse(value))
+                        .sum(),
+                );
+            }
+            VarFunction::Rate(var) => (var, None),
+            VarFunction::Avg(var, window)
+            | VarFunction::Min(var, window)
+            | VarFunction::Max(var, window) => (var, Some(*window)),
+        };
+        if arrived.contains(var) {
+            if let Some(value) = vars.get(var).and_then(parse) {
+                self.samples.push_back((now, value));
+            }
+        }
+        match window {
+            Some(window) => {
+                while self
+                    .samples
+                    .front()
+                    .is_some_and(|(time, _)| now.duration_since(*time) > window)
+                {
+                    self.samples.pop_front();
+                }
+            }
+            None => {
+                while self.samples.len() > 2 {
+                    self.samples.pop_front();
+                }
+            }
+        }
+        let values = self.samples.iter().map(|(_, value)| *value);
+        match function {
+            VarFunction::Rate(_) => {
+                let (first_time, first) = *self.samples.front()?;
+                let (last_time, last) = *self.samples.back()?;
+                let elapsed = last_time.duration_since(first_time).as_secs_f64();
+                // A counter going down was reset, the next sample gives the rate again.
+                if elapsed <= 0.0 || last < first {
+                    return None;
+                }
+                Some((last - first) / elapsed)
+            }
+            VarFunction::Avg(..) if !self.samples.is_empty() => {
+                Some(values.sum::<f64>() / self.samples.len() as f64)
+            }
+            VarFunction::Min(..) => values.reduce(f64::min),
+            VarFunction::Max(..) => values.reduce(f64::max),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves `${value}` of a `[[var]]` to the result of its function.
+struct FunctionContext<'a> {
+    vars: &'a dyn parse::PlaceholderContext,
+    value: &'a String,
+    value_changed: bool,
+}
+
+impl<'a> parse::PlaceholderContext for FunctionContext<'a> {
+    fn get(&self, key: &str) -> Option<&String> {
+        if key == "value" {
+            Some(self.value)
+        } else {
+            self.vars.get(key)
+        }
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        self.vars.keys_with_prefix(prefix)
+    }
+
+    fn changed(&self, key: &str) -> bool {
+        if key == "value" {
+            self.value_changed
+        } else {
+            self.vars.changed(key)
+        }
+    }
+}
+
 fn format_error_str(error_str: &str) -> String {
     use itertools::Itertools;
     error_str
@@ -57,11 +173,66 @@ impl State {
     }
 
     pub fn initialize_vars(&mut self) {
-        self.handle_var_update(Default::default());
+        let mut var_update = VarUpdate::default();
+        if let Some(theme) = &self.config.active_theme {
+            if !self.vars.contains_key(config::THEME_VAR) {
+                var_update.entries.push(UpdateEntry {
+                    var: config::THEME_VAR.into(),
+                    value: theme.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+        self.handle_var_update(var_update);
+    }
+
+    /// Replaces the config after a reload, keeping command vars.
+    pub fn reload(&mut self, config: config::Config<parse::Placeholder>) {
+        let mut removed: Vec<String> = self.config.var_order.clone();
+        removed.extend(
+            self.vars
+                .keys()
+                .filter(|name| name.starts_with("theme."))
+                .cloned(),
+        );
+        if self.config.active_theme != config.active_theme {
+            removed.push(config::THEME_VAR.into());
+        }
+        for var_name in removed.iter() {
+            self.vars.remove(var_name);
+        }
+        self.mark_changed(removed.iter());
+        self.functions.clear();
+        self.config = config;
+        self.config_error = None;
+        self.initialize_vars();
+    }
+
+    /// Whether the var changed after the state had the given `version`.
+    pub fn var_changed_since(&self, name: &str, version: u64) -> bool {
+        self.var_versions
+            .get(name)
+            .is_some_and(|var_version| *var_version > version)
+    }
+
+    fn mark_changed<'a>(&mut self, names: impl Iterator<Item = &'a String>) {
+        let mut names = names.peekable();
+        if names.peek().is_none() {
+            return;
+        }
+        self.version += 1;
+        for name in names {
+            self.var_versions.insert(name.clone(), self.version);
+        }
     }
 
     pub fn build_error_msg(&self) -> Option<ErrorMessage> {
-        if let Some(error) = &self.error {
+        if let Some(error) = &self.config_error {
+            Some(ErrorMessage {
+                source: "config".into(),
+                message: error.clone(),
+            })
+        } else if let Some(error) = &self.error {
             Some(ErrorMessage {
                 source: "oatbar_internal".into(),
                 message: error.clone(),
@@ -82,6 +253,8 @@ impl State {
             Update::MotionUpdate(u) => self.handle_motion_update(u),
             Update::DumpSvg(_, _) => {}
             Update::ForceRedraw => {}
+            Update::Reload(config) => self.reload(*config),
+            Update::ConfigError(error) => self.config_error = Some(format_error_str(&error)),
         }
     }
 
@@ -94,11 +267,51 @@ impl State {
         }
     }
 
+    /// Sets `theme.<key>` vars from the theme selected by the `theme` var.
+    ///
+    /// Keys missing in the selected theme are cleared, so that `def` filters apply.
+    fn update_theme_vars(&mut self, var_snapshot_update: &mut VarSnapshotUpdate) {
+        let name = self
+            .vars
+            .get(config::THEME_VAR)
+            .cloned()
+            .unwrap_or_default();
+        let theme = self.config.themes.get(&name);
+        if theme.is_none() && !name.is_empty() {
+            self.error = Some(format!("Unknown theme: {:?}", name));
+        }
+        let changed: Vec<(String, String)> = self
+            .config
+            .themes
+            .values()
+            .flat_map(|theme| theme.keys())
+            .map(|key| {
+                let value = theme.and_then(|t| t.get(key)).cloned();
+                (format!("theme.{}", key), value.unwrap_or_default())
+            })
+            .filter(|(var, value)| self.vars.get(var) != Some(value))
+            .collect();
+        for (var, value) in changed {
+            self.vars.insert(var.clone(), value.clone());
+            var_snapshot_update.vars.insert(var, value);
+        }
+    }
+
+    /// `prefix` of every `repeat` block.
+    fn repeat_prefixes(&self) -> impl Iterator<Item = &str> {
+        self.config.blocks.values().filter_map(|block| match block {
+            config::Block::Repeat(repeat) => Some(repeat.prefix.as_str()),
+            _ => None,
+        })
+    }
+
     pub fn handle_var_update(&mut self, var_update: VarUpdate) {
         let mut var_snapshot_update = VarSnapshotUpdate {
             vars: Default::default(),
         };
 
+        // Names of all vars in the update, including the ones that did not change.
+        let mut arrived = HashSet::new();
         for update in var_update.entries.into_iter() {
             let mut var = Vec::with_capacity(3);
             if let Some(name) = update.name {
@@ -117,25 +330,98 @@ impl State {
                 .vars
                 .insert(name.clone(), update.value.clone())
                 .unwrap_or_default();
+            arrived.insert(name.clone());
             if old_value != update.value {
                 var_snapshot_update.vars.insert(name, update.value);
             }
         }
 
+        if let Some(command_name) = &var_update.command_name {
+            let prefix = format!("{}:", command_name);
+            let removed_prefixes: Vec<&str> = if var_update.snapshot {
+                vec![prefix.as_str()]
+            } else if var_update.i3bar_row {
+                self.repeat_prefixes()
+                    .filter(|repeat_prefix| repeat_prefix.starts_with(&prefix))
+                    .collect()
+            } else {
+                vec![]
+            };
+            let removed: Vec<String> = self
+                .vars
+                .keys()
+                .filter(|name| {
+                    name.strip_prefix(&prefix)
+                        .is_some_and(|var| !var.starts_with("__"))
+                        && removed_prefixes
+                            .iter()
+                            .any(|removed_prefix| name.starts_with(removed_prefix))
+                        && !arrived.contains(*name)
+                })
+                .cloned()
+                .collect();
+            for name in removed {
+                match &var_update.stale_value {
+                    Some(stale_value) => {
+                        if self.vars.insert(name.clone(), stale_value.clone()).as_ref()
+                            != Some(stale_value)
+                        {
+                            var_snapshot_update.vars.insert(name, stale_value.clone());
+                        }
+                    }
+                    None => {
+                        self.vars.remove(&name);
+                        var_snapshot_update.vars.insert(name, String::new());
+                    }
+                }
+            }
+        }
+
         self.error = None;
+        self.update_theme_vars(&mut var_snapshot_update);
+        // A var can refer to a var declared after it, which changed during the previous update.
+        let previous_vars_version =
+            std::mem::replace(&mut self.previous_vars_version, self.version);
+        let now = Instant::now();
         for var_name in self.config.var_order.iter() {
             let var = self
                 .config
                 .vars
                 .get_mut(var_name)
                 .expect("var from var_order should be present in the map");
-            match var
-                .input
-                .update(&self.vars)
-                .with_context(|| format!("var: '{}'", var.name))
-            {
+            let var_versions = &self.var_versions;
+            let changed = |name: &str| {
+                var_snapshot_update.vars.contains_key(name)
+                    || var_versions
+                        .get(name)
+                        .is_some_and(|version| *version > previous_vars_version)
+            };
+            let vars = parse::PlaceholderContextWithChanges {
+                vars: &self.vars,
+                changed: &changed,
+            };
+            let result = match &var.function {
+                Some(function) => {
+                    let state = self.functions.entry(var.name.clone()).or_default();
+                    let value = state
+                        .update(function, &self.vars, &arrived, now)
+                        .map(|value| value.to_string())
+                        .unwrap_or_default();
+                    let value_changed = value != state.value;
+                    state.value = value;
+                    var.input.update(&FunctionContext {
+                        vars: &vars,
+                        value: &state.value,
+                        value_changed,
+                    })
+                }
+                None => var.input.update(&vars),
+            }
+            .with_context(|| format!("var: '{}'", var.name));
+            match result {
                 Ok(updated) if updated => {
                     let processed: &str = &var.input.value;
+                    arrived.insert(var.name.clone());
                     self.vars.insert(var.name.clone(), processed.to_string());
                     var_snapshot_update
                         .vars
@@ -148,7 +434,7 @@ impl State {
             }
         }
 
-        if let Some(command_name) = var_update.command_name {
+        if let (false, Some(command_name)) = (var_update.keep_error, var_update.command_name) {
             if let Some(error) = var_update.error {
                 self.command_errors.insert(
                     command_name,
@@ -159,6 +445,8 @@ impl State {
             }
         }
 
+        self.mark_changed(var_snapshot_update.vars.keys());
+
         if !var_snapshot_update.vars.is_empty() {
             for rx in self.var_snapshot_updates_tx.iter() {
                 if let Err(e) = rx.send(var_snapshot_update.clone()) {
@@ -192,6 +480,18 @@ pub struct VarUpdate {
     pub command_name: Option<String>,
     pub entries: Vec<UpdateEntry>,
     pub error: Option<String>,
+    /// Entries are the complete set of the command's vars, other vars of the command are removed.
+    ///
+    /// Vars starting with `__` are kept, they are maintained by `oatbar` itself.
+    pub snapshot: bool,
+    /// With `snapshot`, other vars of the command are set to this value instead of being removed.
+    pub stale_value: Option<String>,
+    /// Entries are a complete i3bar row of the command, so its vars under the `prefix` of a
+    /// `repeat` block are removed if the row does not have them anymore.
+    pub i3bar_row: bool,
+    /// The update is bookkeeping by `oatbar`, not output of the command, so the error of the
+    /// command stays.
+    pub keep_error: bool,
 }
 
 #[derive(Debug)]
@@ -200,6 +500,8 @@ pub enum Update {
     MotionUpdate(MotionUpdate),
     DumpSvg(String, usize),
     ForceRedraw,
+    Reload(Box<config::Config<parse::Placeholder>>),
+    ConfigError(String),
 }
 
 #[derive(Debug, Default, Clone)]
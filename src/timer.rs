@@ -17,6 +17,17 @@ impl Timer {
     pub fn new<F>(name: &str, at: SystemTime, f: F) -> anyhow::Result<Self>
     where
         F: Fn() + Send + 'static,
+    {
+        Self::repeating(name, at, move || {
+            f();
+            None
+        })
+    }
+
+    /// Calls `f` at `at` and then again at every time it returns, until it returns `None`.
+    pub fn repeating<F>(name: &str, at: SystemTime, mut f: F) -> anyhow::Result<Self>
+    where
+        F: FnMut() -> Option<SystemTime> + Send + 'static,
     {
         let timer = Timer {
             at: Arc::new(Mutex::new(at)),
@@ -30,10 +41,13 @@ impl Timer {
                         sleep(duration);
                         Ok(true)
                     }
-                    Err(_) => {
-                        f();
-                        Ok(false)
-                    }
+                    Err(_) => match f() {
+                        Some(next) => {
+                            timer.set_at(next);
+                            Ok(true)
+                        }
+                        None => Ok(false),
+                    },
                 }
             })?;
         }
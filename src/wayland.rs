@@ -9,7 +9,7 @@ use crate::{
     engine::Engine,
     notify, parse,
     popup_visibility::PopupManager,
-    state, thread,
+    source, state, thread,
 };
 use sct::reexports::client as smithay_client;
 use sct::shell::WaylandSurface;
@@ -52,6 +52,8 @@ impl WaylandWindow {
 
         update_tx: crossbeam_channel::Sender<state::Update>,
         notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
         qh: &smithay_client::QueueHandle<WaylandEngine>,
         compositor_state: &sct::compositor::CompositorState,
         layer_shell: &sct::shell::wlr_layer::LayerShell,
@@ -101,7 +103,13 @@ impl WaylandWindow {
             smithay_client_toolkit::shell::wlr_layer::KeyboardInteractivity::None,
         );
         layer_surface.commit();
-        let bar = bar::Bar::new(config, bar_config.clone(), notifier.clone())?;
+        let bar = bar::Bar::new(
+            config,
+            bar_config.clone(),
+            notifier.clone(),
+            clicks,
+            stopper,
+        )?;
         let visible = !bar_config.popup;
 
         let font_cache = Arc::new(Mutex::new(drawing::FontCache::new()));
@@ -208,7 +216,7 @@ impl WaylandWindow {
         let pointer_position = state.pointer_position.get(&self.name).copied();
         let mut error = state.build_error_msg();
 
-        let updates = match self.bar.update(&mut context, &state.vars, pointer_position) {
+        let updates = match self.bar.update(&mut context, &state, pointer_position) {
             Ok(updates) => updates,
             Err(e) => {
                 error = Some(state::ErrorMessage {
@@ -250,6 +258,7 @@ impl WaylandWindow {
                 self.visible = visible;
             }
         }
+        self.bar.report_shown(self.visible);
 
         if !self.bar_config.popup_at_edge && !self.visible {
             self.layer_surface.wl_surface().attach(None, 0, 0);
@@ -310,6 +319,21 @@ impl WaylandWindow {
         self.layer_surface.wl_surface().commit();
         Ok(())
     }
+    /// Replaces the bar shown in the window, keeping the surface.
+    pub fn rebuild(
+        &mut self,
+        config: &config::Config<parse::Placeholder>,
+        bar_config: config::Bar<parse::Placeholder>,
+        notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
+    ) -> anyhow::Result<()> {
+        tracing::info!("Reloading wayland window {}", self.name);
+        self.bar = bar::Bar::new(config, bar_config.clone(), notifier, clicks, stopper)?;
+        self.bar_config = bar_config;
+        Ok(())
+    }
+
     pub fn wl_surface(&self) -> &wayland_client::protocol::wl_surface::WlSurface {
         self.layer_surface.wl_surface()
     }
@@ -375,6 +399,8 @@ pub struct WaylandEngine {
     >,
     config: config::Config<parse::Placeholder>,
     notifier: notify::Notifier,
+    clicks: source::Clicks,
+    stopper: source::Stopper,
     qh: smithay_client::QueueHandle<WaylandEngine>,
     pointer_surface: Option<wayland_client::protocol::wl_surface::WlSurface>,
     last_pointer_pos: (f64, f64),
@@ -407,16 +433,16 @@ impl sct::seat::pointer::PointerHandler for WaylandEngine {
                         }
                     }
                 }
-                PointerEventKind::Leave { .. } => {
-                    if self.pointer_surface.as_ref() == Some(&event.surface) {
-                        self.pointer_surface = None;
-                        for window in self.windows.values_mut() {
-                            if window.wl_surface() == &event.surface {
-                                if let Err(e) = window.handle_motion_leave() {
-                                    tracing::error!("handle_motion_leave error: {}", e);
-                                }
-                                break;
+                PointerEventKind::Leave { .. }
+                    if self.pointer_surface.as_ref() == Some(&event.surface) =>
+                {
+                    self.pointer_surface = None;
+                    for window in self.windows.values_mut() {
+                        if window.wl_surface() == &event.surface {
+                            if let Err(e) = window.handle_motion_leave() {
+                                tracing::error!("handle_motion_leave error: {}", e);
                             }
+                            break;
                         }
                     }
                 }
@@ -503,6 +529,8 @@ impl WaylandEngine {
         config: config::Config<parse::Placeholder>,
         initial_state: state::State,
         notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
     ) -> anyhow::Result<Self> {
         let state = Arc::new(RwLock::new(initial_state));
         let (update_tx, update_rx) = crossbeam_channel::unbounded();
@@ -548,11 +576,49 @@ impl WaylandEngine {
             output_infos: HashMap::new(),
             config,
             notifier,
+            clicks,
+            stopper,
         };
         engine.reassess_windows();
         Ok(engine)
     }
 
+    /// Shows the new config in the existing windows, recreating only those
+    /// whose monitor or geometry changed, so that the bars do not blank.
+    fn reload(&mut self, config: config::Config<parse::Placeholder>) {
+        let outdated: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|((index, _), window)| {
+                !config
+                    .bar
+                    .get(*index)
+                    .is_some_and(|bar| window.bar_config.same_window(bar))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in outdated {
+            if let Some(removed) = self.windows.remove(&key) {
+                if self.pointer_surface.as_ref() == Some(removed.wl_surface()) {
+                    self.pointer_surface = None;
+                }
+            }
+        }
+        for ((index, _), window) in self.windows.iter_mut() {
+            if let Err(e) = window.rebuild(
+                &config,
+                config.bar[*index].clone(),
+                self.notifier.clone(),
+                self.clicks.clone(),
+                self.stopper.clone(),
+            ) {
+                tracing::error!("Unable to reload bar {}: {:?}", index, e);
+            }
+        }
+        self.config = config;
+        self.reassess_windows();
+    }
+
     fn reassess_windows(&mut self) {
         let mut windows_to_keep =
             HashSet::<(usize, smithay_client::protocol::wl_output::WlOutput)>::new();
@@ -618,6 +684,8 @@ impl WaylandEngine {
                     self.state.clone(),
                     self.update_tx.clone(),
                     self.notifier.clone(),
+                    self.clicks.clone(),
+                    self.stopper.clone(),
                     &self.qh,
                     &self.compositor_state,
                     &self.layer_shell,
@@ -692,10 +760,17 @@ impl Engine for WaylandEngine {
                         return;
                     }
                     tracing::trace!("state_update: {:?}", state_update);
+                    let reloaded_config = match &state_update {
+                        state::Update::Reload(config) => Some(config.as_ref().clone()),
+                        _ => None,
+                    };
                     {
                         let mut state = engine.state.write().unwrap();
                         state.handle_state_update(state_update);
                     }
+                    if let Some(config) = reloaded_config {
+                        engine.reload(config);
+                    }
                     for window in engine.windows.values_mut() {
                         if let Err(err) = window.draw(
                             &engine.qh,
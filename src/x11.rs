@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 use anyhow::Context;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex, RwLock},
 };
 use xcb::{x, xinput, Xid};
@@ -10,7 +10,7 @@ use crate::{
     bar::{self, BarUpdates, BlockUpdates},
     config, drawing,
     engine::Engine,
-    notify, parse, popup_visibility, state, thread, wmready, xutils,
+    notify, parse, popup_visibility, source, state, thread, wmready, xutils,
 };
 use tracing::*;
 
@@ -27,6 +27,7 @@ pub struct Window {
     shape_buffer_surface: cairo::XCBSurface,
     shape_buffer_pixmap: x::Pixmap,
     swap_gc: x::Gcontext,
+    colormap: x::Colormap,
     bar: bar::Bar,
     // bar_index: usize,
     bar_config: config::Bar<parse::Placeholder>,
@@ -50,6 +51,8 @@ impl Window {
         state_update_tx: crossbeam_channel::Sender<state::Update>,
         wm_info: &wmready::WMInfo,
         notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
         popup_manager_mutex: Arc<Mutex<popup_visibility::PopupManager>>,
         update_tx: crossbeam_channel::Sender<state::Update>,
     ) -> anyhow::Result<Self> {
@@ -308,7 +311,13 @@ impl Window {
         }
         conn.flush()?;
 
-        let bar = bar::Bar::new(config, bar_config.clone(), notifier.clone())?;
+        let bar = bar::Bar::new(
+            config,
+            bar_config.clone(),
+            notifier.clone(),
+            clicks,
+            stopper,
+        )?;
 
         Ok(Self {
             conn: conn.clone(),
@@ -323,6 +332,7 @@ impl Window {
             shape_buffer_surface,
             shape_buffer_pixmap,
             swap_gc,
+            colormap: cid,
             // bar_index,
             bar,
             state,
@@ -335,6 +345,48 @@ impl Window {
         })
     }
 
+    /// Replaces the bar shown in the window, keeping the window itself.
+    pub fn rebuild(
+        &mut self,
+        config: &config::Config<parse::Placeholder>,
+        bar_config: config::Bar<parse::Placeholder>,
+        notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
+    ) -> anyhow::Result<()> {
+        info!("Reloading bar {:?}", self.name);
+        self.bar = bar::Bar::new(config, bar_config.clone(), notifier, clicks, stopper)?;
+        self.bar_config = bar_config;
+        Ok(())
+    }
+
+    pub fn destroy(self) -> anyhow::Result<()> {
+        self.back_buffer_surface.finish();
+        self.shape_buffer_surface.finish();
+        xutils::send(&self.conn, &x::DestroyWindow { window: self.id })?;
+        xutils::send(
+            &self.conn,
+            &x::FreePixmap {
+                pixmap: self.back_buffer_pixmap,
+            },
+        )?;
+        xutils::send(
+            &self.conn,
+            &x::FreePixmap {
+                pixmap: self.shape_buffer_pixmap,
+            },
+        )?;
+        xutils::send(&self.conn, &x::FreeGc { gc: self.swap_gc })?;
+        xutils::send(
+            &self.conn,
+            &x::FreeColormap {
+                cmap: self.colormap,
+            },
+        )?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
     fn render_bar(&mut self, redraw: &bar::RedrawScope) -> anyhow::Result<()> {
         self.bar.render(&self.back_buffer_context, redraw)?;
         self.bar.render(&self.shape_buffer_context, redraw)?;
@@ -354,26 +406,25 @@ impl Window {
         let pointer_position = state.pointer_position.get(&self.name).copied();
         let mut error = state.build_error_msg();
 
-        let updates =
-            match self
-                .bar
-                .update(&mut self.back_buffer_context, &state.vars, pointer_position)
-            {
-                Ok(updates) => updates,
-                Err(e) => {
-                    error = Some(state::ErrorMessage {
-                        source: "bar_update".into(),
-                        message: format!("Error: {:?}", e),
-                    });
-                    BarUpdates {
-                        block_updates: BlockUpdates {
-                            redraw: bar::RedrawScope::All,
-                            popup: Default::default(),
-                        },
-                        visible_from_vars: None,
-                    }
+        let updates = match self
+            .bar
+            .update(&mut self.back_buffer_context, &state, pointer_position)
+        {
+            Ok(updates) => updates,
+            Err(e) => {
+                error = Some(state::ErrorMessage {
+                    source: "bar_update".into(),
+                    message: format!("Error: {:?}", e),
+                });
+                BarUpdates {
+                    block_updates: BlockUpdates {
+                        redraw: bar::RedrawScope::All,
+                        popup: Default::default(),
+                    },
+                    visible_from_vars: None,
                 }
-            };
+            }
+        };
 
         self.bar
             .set_error(&mut self.back_buffer_context, error.clone());
@@ -400,6 +451,7 @@ impl Window {
                 }
             }
         }
+        self.bar.report_shown(self.visible);
         let mut redraw = updates.block_updates.redraw;
         let layout_changed = self.bar.layout_groups(self.width as f64);
         if layout_changed {
@@ -610,6 +662,10 @@ pub struct XOrgEngine {
     pub update_tx: crossbeam_channel::Sender<state::Update>,
     update_rx: Option<crossbeam_channel::Receiver<state::Update>>,
     popup_manager: std::sync::Arc<std::sync::Mutex<popup_visibility::PopupManager>>,
+    wm_info: wmready::WMInfo,
+    notifier: notify::Notifier,
+    clicks: source::Clicks,
+    stopper: source::Stopper,
     // Set during run().
     loop_handle: Option<calloop::LoopHandle<'static, Self>>,
 }
@@ -619,6 +675,8 @@ impl XOrgEngine {
         config: config::Config<parse::Placeholder>,
         initial_state: state::State,
         notifier: notify::Notifier,
+        clicks: source::Clicks,
+        stopper: source::Stopper,
     ) -> anyhow::Result<Self> {
         let state = Arc::new(RwLock::new(initial_state));
         let (update_tx, update_rx) = crossbeam_channel::unbounded();
@@ -655,30 +713,9 @@ impl XOrgEngine {
             .context("init xinput 2.0 extension")?
         );
 
-        let mut windows = HashMap::new();
-
-        for (index, bar) in config.bar.iter().enumerate() {
-            let window = Window::create_and_show(
-                format!("bar{}", index),
-                // index,
-                &config,
-                bar.clone(),
-                conn.clone(),
-                state.clone(),
-                update_tx.clone(),
-                &wm_info,
-                notifier.clone(),
-                popup_manager.clone(),
-                update_tx.clone(),
-            )?;
-            windows.insert(window.id, window);
-        }
-
-        let window_ids = windows.keys().cloned().collect();
-
-        Ok(Self {
-            windows,
-            window_ids,
+        let mut engine = Self {
+            windows: HashMap::new(),
+            window_ids: vec![],
             state,
             conn,
             screen,
@@ -686,7 +723,84 @@ impl XOrgEngine {
             update_rx: Some(update_rx),
             loop_handle: None,
             popup_manager,
-        })
+            wm_info,
+            notifier,
+            clicks,
+            stopper,
+        };
+        engine.create_windows(&config)?;
+        Ok(engine)
+    }
+
+    fn create_windows(
+        &mut self,
+        config: &config::Config<parse::Placeholder>,
+    ) -> anyhow::Result<()> {
+        for index in 0..config.bar.len() {
+            self.create_window(config, index)?;
+        }
+        self.window_ids = self.windows.keys().cloned().collect();
+        Ok(())
+    }
+
+    fn create_window(
+        &mut self,
+        config: &config::Config<parse::Placeholder>,
+        index: usize,
+    ) -> anyhow::Result<()> {
+        let window = Window::create_and_show(
+            format!("bar{}", index),
+            // index,
+            config,
+            config.bar[index].clone(),
+            self.conn.clone(),
+            self.state.clone(),
+            self.update_tx.clone(),
+            &self.wm_info,
+            self.notifier.clone(),
+            self.clicks.clone(),
+            self.stopper.clone(),
+            self.popup_manager.clone(),
+            self.update_tx.clone(),
+        )?;
+        self.windows.insert(window.id, window);
+        Ok(())
+    }
+
+    /// Shows the new config in the existing windows, recreating only those
+    /// whose monitor or geometry changed, so that the bars do not blank.
+    fn reload(&mut self, config: &config::Config<parse::Placeholder>) -> anyhow::Result<()> {
+        let mut reused = HashSet::new();
+        let ids: Vec<x::Window> = self.windows.keys().cloned().collect();
+        for id in ids {
+            let index = self.windows[&id].bar_config.index;
+            match config.bar.get(index) {
+                Some(bar) if self.windows[&id].bar_config.same_window(bar) => {
+                    if let Some(window) = self.windows.get_mut(&id) {
+                        window.rebuild(
+                            config,
+                            bar.clone(),
+                            self.notifier.clone(),
+                            self.clicks.clone(),
+                            self.stopper.clone(),
+                        )?;
+                    }
+                    reused.insert(index);
+                }
+                _ => {
+                    if let Some(window) = self.windows.remove(&id) {
+                        window.destroy()?;
+                    }
+                }
+            }
+        }
+        for index in 0..config.bar.len() {
+            if !reused.contains(&index) {
+                self.create_window(config, index)?;
+            }
+        }
+        self.window_ids = self.windows.keys().cloned().collect();
+        Ok(())
     }
 
     fn handle_event(&mut self, event: &xcb::Event) -> anyhow::Result<()> {
@@ -839,10 +953,19 @@ impl Engine for XOrgEngine {
                             }
                             return;
                         }
+                        let reloaded_config = match &state_update {
+                            state::Update::Reload(config) => Some(config.as_ref().clone()),
+                            _ => None,
+                        };
                         {
                             let mut state = engine.state.write().unwrap();
                             state.handle_state_update(state_update);
                         }
+                        if let Some(config) = reloaded_config {
+                            if let Err(e) = engine.reload(&config) {
+                                tracing::error!("Failed to reload bars: {:?}", e);
+                            }
+                        }
                         for window in engine.windows.values_mut() {
                             if let Err(e) = window.render(&mut engine.loop_handle) {
                                 tracing::error!("Failed to render bar {:?}", e);